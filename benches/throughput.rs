@@ -0,0 +1,156 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use seraphic::{
+    derive::{RequestWrapper, ResponseWrapper, RpcNamespace, RpcRequest},
+    packet::{PacketRead, TcpPacket},
+    tokio::{AsyncServerConnection, Server, ServerConnectionHandler, ServerHandlerResult},
+    RequestWrapper, ResponseWrapper,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use tokio::{net::TcpStream, runtime::Runtime};
+
+#[derive(RpcNamespace, Clone, Copy, PartialEq, Eq)]
+pub enum BenchNS {
+    Bench,
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "BenchNS:bench")]
+pub struct EchoRequest {
+    data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EchoResponse {
+    data: String,
+}
+
+#[derive(Debug, Clone, RequestWrapper, PartialEq)]
+pub enum MyRequest {
+    Echo(EchoRequest),
+}
+
+#[derive(Debug, Clone, ResponseWrapper, PartialEq)]
+pub enum MyResponse {
+    Echo(EchoResponse),
+}
+
+type Message = seraphic::Message<MyRequest, MyResponse>;
+type MessagePacket = TcpPacket<Message>;
+
+const SIZES: [(&str, usize); 3] = [("100B", 100), ("10KB", 10_000), ("1MB", 1_000_000)];
+
+fn request(size: usize) -> Message {
+    MyRequest::from(EchoRequest {
+        data: "x".repeat(size),
+    })
+    .into_message(0)
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn packet_roundtrip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet_roundtrip");
+    for (name, size) in SIZES {
+        let msg = request(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("sync", name), &msg, |b, msg| {
+            b.iter(|| {
+                let mut buf = vec![];
+                MessagePacket::write(&mut buf, msg).unwrap();
+                MessagePacket::read(&mut Cursor::new(buf)).unwrap()
+            })
+        });
+
+        let rt = runtime();
+        group.bench_with_input(BenchmarkId::new("async", name), &msg, |b, msg| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+                    let (written, read) = tokio::join!(
+                        MessagePacket::async_write(&mut client, msg),
+                        MessagePacket::async_read(&mut server)
+                    );
+                    written.unwrap();
+                    read.unwrap()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn message_serde(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message_serde");
+    for (name, size) in SIZES {
+        let msg = request(size);
+        let json = serde_json::to_vec(&msg).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("serialize", name), &msg, |b, msg| {
+            b.iter(|| serde_json::to_vec(msg).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("deserialize", name), &json, |b, json| {
+            b.iter(|| serde_json::from_slice::<Message>(json).unwrap())
+        });
+    }
+    group.finish();
+}
+
+struct EchoHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for EchoHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        while let Some(msg) = conn.recv().await? {
+            if let Message::Req {
+                id,
+                req: MyRequest::Echo(EchoRequest { data }),
+            } = msg
+            {
+                let res = MyResponse::from(EchoResponse { data }).into_message(id);
+                conn.send(&res).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A request sent to a `Server` over localhost and its response read back
+fn server_roundtrip(c: &mut Criterion) {
+    let rt = runtime();
+    let mut stream = rt.block_on(async {
+        let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+        TcpStream::connect(addr).await.unwrap()
+    });
+
+    let mut group = c.benchmark_group("server_roundtrip");
+    for (name, size) in SIZES {
+        let msg = request(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &msg, |b, msg| {
+            b.iter(|| {
+                rt.block_on(async {
+                    MessagePacket::async_write(&mut stream, msg).await.unwrap();
+                    match MessagePacket::async_read(&mut stream).await.unwrap() {
+                        PacketRead::Message(res) => res,
+                        other => panic!("expected a response, got: {other:?}"),
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, packet_roundtrip, message_serde, server_roundtrip);
+criterion_main!(benches);
@@ -1,8 +1,8 @@
 use core::panic;
-use darling::FromDeriveInput;
+use darling::{FromDeriveInput, FromVariant};
 use proc_macro::{self, TokenStream};
+use proc_macro_crate::{crate_name, FoundCrate};
 use quote::{format_ident, quote};
-use std::hash::Hash;
 use syn::{parse_macro_input, Data, DataEnum, DataStruct, DeriveInput, TypePath};
 
 // https://github.com/imbolc/rust-derive-macro-guide
@@ -12,127 +12,554 @@ struct Opts {
     // formatted "type:variant"
     namespace: String,
     response: Option<String>,
+    strict_params: bool,
+    // "camelCase" (default), "snake_case" or "kebab-case"
+    naming: Option<String>,
+    // emit a `Debug` impl so it doesn't have to be derived separately
+    impl_debug: bool,
+    // fail to compile if the struct has any fields
+    assert_empty_params: bool,
+    // don't emit a `PartialEq` impl, for when it is implemented by hand or derived next to
+    // `RpcRequest`, which would otherwise conflict with ours
+    no_eq: bool,
+}
+
+fn debug_fields(
+    name: &str,
+    fields: &syn::Fields,
+    accessor: impl Fn(usize, &syn::Field) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    match fields {
+        syn::Fields::Named(named) => {
+            let fields = named.named.iter().enumerate().map(|(i, f)| {
+                let field_name = f.ident.as_ref().unwrap().to_string();
+                let value = accessor(i, f);
+                quote! { .field(#field_name, #value) }
+            });
+            quote! { f.debug_struct(#name) #(#fields)* .finish() }
+        }
+        syn::Fields::Unnamed(unnamed) => {
+            let fields = unnamed.unnamed.iter().enumerate().map(|(i, f)| {
+                let value = accessor(i, f);
+                quote! { .field(#value) }
+            });
+            quote! { f.debug_tuple(#name) #(#fields)* .finish() }
+        }
+        syn::Fields::Unit => quote! { f.write_str(#name) },
+    }
+}
+
+/// The same output as `#[derive(Debug)]`
+fn debug_impl(ident: &syn::Ident, data: &syn::Data) -> proc_macro2::TokenStream {
+    let body = match data {
+        syn::Data::Struct(DataStruct { fields, .. }) => {
+            debug_fields(&ident.to_string(), fields, |i, f| match &f.ident {
+                Some(id) => quote! { &self.#id },
+                None => {
+                    let index = syn::Index::from(i);
+                    quote! { &self.#index }
+                }
+            })
+        }
+        syn::Data::Enum(DataEnum { variants, .. }) => {
+            let arms = variants.iter().map(|v| {
+                let id = &v.ident;
+                let binding = |i: usize, f: &syn::Field| match &f.ident {
+                    Some(id) => quote! { #id },
+                    None => {
+                        let id = format_ident!("__{i}");
+                        quote! { #id }
+                    }
+                };
+                let bindings = v.fields.iter().enumerate().map(|(i, f)| binding(i, f));
+                let pattern = match &v.fields {
+                    syn::Fields::Named(_) => quote! { Self::#id { #(#bindings),* } },
+                    syn::Fields::Unnamed(_) => quote! { Self::#id ( #(#bindings),* ) },
+                    syn::Fields::Unit => quote! { Self::#id },
+                };
+                let body = debug_fields(&id.to_string(), &v.fields, binding);
+                quote! { #pattern => #body, }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        _ => panic!("cannot derive this on anything but a struct or an enum"),
+    };
+    quote! {
+        impl ::core::fmt::Debug for #ident {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                #body
+            }
+        }
+    }
+}
+
+/// The same output as `#[derive(PartialEq)]`, with a `PartialEq` bound on every field type
+fn eq_impl(
+    ident: &syn::Ident,
+    generics: &syn::Generics,
+    data: &syn::Data,
+) -> proc_macro2::TokenStream {
+    // `Self::Variant { a: __self_0, .. }` for one side of the comparison
+    let pattern = |variant: &syn::Ident, fields: &syn::Fields, prefix: &str| {
+        let bindings = (0..fields.len()).map(|i| format_ident!("__{prefix}_{i}"));
+        match fields {
+            syn::Fields::Named(named) => {
+                let names = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+                quote! { Self::#variant { #(#names: #bindings),* } }
+            }
+            syn::Fields::Unnamed(_) => quote! { Self::#variant ( #(#bindings),* ) },
+            syn::Fields::Unit => quote! { Self::#variant },
+        }
+    };
+
+    let (body, field_types): (_, Vec<&syn::Type>) = match data {
+        syn::Data::Struct(DataStruct { fields, .. }) => {
+            let comparisons = fields.iter().enumerate().map(|(i, f)| match &f.ident {
+                Some(id) => quote! { && self.#id == other.#id },
+                None => {
+                    let index = syn::Index::from(i);
+                    quote! { && self.#index == other.#index }
+                }
+            });
+            (
+                quote! { true #(#comparisons)* },
+                fields.iter().map(|f| &f.ty).collect(),
+            )
+        }
+        syn::Data::Enum(DataEnum { variants, .. }) => {
+            let arms = variants.iter().map(|v| {
+                let lhs = pattern(&v.ident, &v.fields, "self");
+                let rhs = pattern(&v.ident, &v.fields, "other");
+                let comparisons = (0..v.fields.len()).map(|i| {
+                    let (l, r) = (format_ident!("__self_{i}"), format_ident!("__other_{i}"));
+                    quote! { && #l == #r }
+                });
+                quote! { (#lhs, #rhs) => true #(#comparisons)*, }
+            });
+            (
+                quote! {
+                    #[allow(unreachable_patterns)]
+                    match (self, other) {
+                        #(#arms)*
+                        _ => false,
+                    }
+                },
+                variants
+                    .iter()
+                    .flat_map(|v| v.fields.iter().map(|f| &f.ty))
+                    .collect(),
+            )
+        }
+        _ => panic!("cannot derive this on anything but a struct or an enum"),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut predicates = where_clause
+        .map(|w| w.predicates.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    if !generics.params.is_empty() {
+        predicates.extend(
+            field_types
+                .into_iter()
+                .map(|ty| syn::parse_quote! { #ty: ::core::cmp::PartialEq }),
+        );
+    }
+    quote! {
+        impl #impl_generics ::core::cmp::PartialEq for #ident #ty_generics
+        where #(#predicates),*
+        {
+            fn eq(&self, other: &Self) -> bool {
+                #body
+            }
+        }
+    }
+}
+
+/// Path to the `seraphic` crate, which may have been renamed in the user's Cargo.toml
+fn crate_path() -> proc_macro2::TokenStream {
+    match crate_name("seraphic") {
+        Ok(FoundCrate::Name(name)) => {
+            let name = format_ident!("{name}");
+            quote! { ::#name }
+        }
+        // seraphic itself declares `extern crate self as seraphic`
+        Ok(FoundCrate::Itself) | Err(_) => quote! { ::seraphic },
+    }
+}
+
+/// `FooResponse` -> `foo`, the identity of a derived response and of one implied by a request
+fn response_identity(ident: &syn::Ident) -> syn::Result<String> {
+    let name = ident.to_string();
+    match name.strip_suffix("Response").map(str::chars).as_mut() {
+        Some(chars) => match chars.next() {
+            Some(first) => Ok(first.to_lowercase().chain(chars).collect()),
+            None => Err(syn::Error::new_spanned(
+                ident,
+                "a response named just 'Response' needs an identity, pass one with #[rpc_response(identity = \"...\")]",
+            )),
+        },
+        None => Err(syn::Error::new_spanned(
+            ident,
+            "make sure to put 'Response' at the end of your struct name or pass an identity with #[rpc_response(identity = \"...\")]",
+        )),
+    }
+}
+
+/// `GetUser` -> `get{sep}user`
+fn separated(name: &str, sep: char) -> String {
+    let mut out = String::new();
+    for (i, ch) in name.chars().enumerate() {
+        if ch.is_uppercase() && i > 0 {
+            out.push(sep);
+        }
+        out.extend(ch.to_lowercase());
+    }
+    out
 }
 
 #[proc_macro_derive(RpcRequest, attributes(rpc_request))]
 pub fn derive_rpc_req(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input);
-    let opts = Opts::from_derive_input(&input).expect("Wrong options");
-    let DeriveInput { ident, data, .. } = input;
-    match data {
-        syn::Data::Struct(DataStruct { fields, .. }) => {
-            let name = format!("{ident}");
-            let name_no_suffix = name
-                .strip_suffix("Request")
-                .expect("make sure to put 'Request' at the end of your struct name");
-            // let struct_name = format_ident!("{}", name_no_suffix);
+    let opts = match Opts::from_derive_input(&input) {
+        Ok(opts) => opts,
+        Err(err) => return err.write_errors().into(),
+    };
+    let DeriveInput {
+        ident,
+        generics,
+        data,
+        ..
+    } = input;
+    let krate = crate_path();
+    let name = format!("{ident}");
+    let Some(name_no_suffix) = name.strip_suffix("Request").filter(|n| !n.is_empty()) else {
+        return syn::Error::new_spanned(
+            &ident,
+            "make sure to put 'Request' at the end of your struct name",
+        )
+        .to_compile_error()
+        .into();
+    };
+    // let struct_name = format_ident!("{}", name_no_suffix);
+    let method = match opts.naming.as_deref() {
+        None | Some("camelCase") => {
             let first_char = name_no_suffix
                 .chars()
                 .next()
                 .unwrap()
                 .to_owned()
                 .to_lowercase();
-            let method = format!("{first_char}{}", &name_no_suffix[1..]);
-
+            format!("{first_char}{}", &name_no_suffix[1..])
+        }
+        Some("snake_case") => separated(name_no_suffix, '_'),
+        Some("kebab-case") => separated(name_no_suffix, '-'),
+        Some(other) => {
+            return syn::Error::new_spanned(
+                &ident,
+                format!(
+                    "unknown naming '{other}', expected 'camelCase', 'snake_case' or 'kebab-case'"
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let debug = if opts.impl_debug {
+        debug_impl(&ident, &data)
+    } else {
+        quote! {}
+    };
+
+    let eq = if opts.no_eq {
+        quote! {}
+    } else {
+        eq_impl(&ident, &generics, &data)
+    };
+
+    let hash_params = match &data {
+        syn::Data::Struct(DataStruct {
+            fields: syn::Fields::Named(named),
+            ..
+        }) => {
+            let hashes = named.named.iter().map(|f| {
+                let id = f.ident.as_ref().unwrap();
+                let name = id.to_string();
+                quote! {
+                    ::core::hash::Hasher::write(&mut hasher, #name.as_bytes());
+                    #krate::__private::hash_json(&mut hasher, &self.#id);
+                }
+            });
+            quote! {
+                fn hash_params(&self) -> u64 {
+                    #[allow(unused_mut)]
+                    let mut hasher = #krate::__private::ParamsHasher::default();
+                    #(#hashes)*
+                    ::core::hash::Hasher::finish(&hasher)
+                }
+            }
+        }
+        // anything else is hashed whole, by the trait's default
+        _ => quote! {},
+    };
+
+    let strict_const = if opts.strict_params {
+        quote! { const STRICT_PARAMS: bool = true; }
+    } else {
+        quote! {}
+    };
+
+    let from_json = match data {
+        syn::Data::Struct(DataStruct { fields, .. })
+            if opts.assert_empty_params && !fields.is_empty() =>
+        {
+            return syn::Error::new_spanned(
+                fields,
+                "`assert_empty_params` is set, this request must not have any fields",
+            )
+            .to_compile_error()
+            .into();
+        }
+        // nothing to parse, unless unknown params have to be rejected
+        syn::Data::Struct(DataStruct { fields, .. })
+            if fields.is_empty() && !opts.strict_params =>
+        {
+            quote! {
+              fn try_from_json(_json: &#krate::__private::serde_json::Value) -> ::core::result::Result<Self, #krate::SeraphicError> {
+                    Ok(Self {})
+              }
+              fn try_from_params_array(_arr: &[#krate::__private::serde_json::Value]) -> ::core::result::Result<Self, #krate::SeraphicError> {
+                    Ok(Self {})
+              }
+            }
+        }
+        syn::Data::Struct(DataStruct { fields, .. }) => {
             let mut from_json_body = quote! {};
+            let mut from_array_body = quote! {};
             let mut create_self_body = quote! {};
+            let mut field_names = vec![];
 
-            for f in fields {
+            for (i, f) in fields.into_iter().enumerate() {
                 let id = f.ident.unwrap();
-                let json_name = format_ident!("{}_json", id);
+                let ty = f.ty;
+                field_names.push(format!("{id}"));
                 let id_string = format!("{id}");
                 let not_exist = format!("field '{id_string}' does not exist");
-                let not_deserialize = format!("field '{id_string}' does not implement deserialize");
+                let not_deserialize =
+                    format!("field '{id_string}' failed to deserialize as {{}}: {{}}");
+                let not_exist_positional =
+                    format!("positional param {i} ('{id_string}') does not exist");
+                let from_value = quote! {
+                    Some(value) => #krate::__private::serde_json::from_value::<#ty>(value.clone())
+                        .map_err(|err| __errors.push(#krate::__private::format!(#not_deserialize, ::core::any::type_name::<#ty>(), err)))
+                        .ok(),
+                };
                 from_json_body = quote! {
                     #from_json_body
-                    let #json_name = json.get(#id_string).ok_or(#not_exist)?.to_owned();
-                    let #id = serde_json::from_value(#json_name).map_err(|_|#not_deserialize)?;
+                    let #id = match json.get(#id_string) {
+                        #from_value
+                        None => {
+                            __errors.push(#krate::__private::String::from(#not_exist));
+                            None
+                        }
+                    };
+                };
+                from_array_body = quote! {
+                    #from_array_body
+                    let #id = match arr.get(#i) {
+                        #from_value
+                        None => {
+                            __errors.push(#krate::__private::String::from(#not_exist_positional));
+                            None
+                        }
+                    };
                 };
 
                 create_self_body = quote! {
                     #create_self_body
-                    #id,
+                    #id: #id.unwrap(),
                 }
             }
 
+            // every problem with the params is reported at once
             let create_self = quote! {
+                if !__errors.is_empty() {
+                    return Err(#krate::__private::invalid_params(__errors));
+                }
                 Ok(Self {
                     #create_self_body
                 })
             };
+            let init_errors = quote! {
+                #[allow(unused_mut)]
+                let mut __errors: #krate::__private::Vec<#krate::__private::String> = #krate::__private::Vec::new();
+            };
 
-            let from_json = quote! {
-              fn try_from_json(json: &serde_json::Value) -> std::result::Result<Self,Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let (strict_object_check, strict_array_check) = if opts.strict_params {
+                let field_count = field_names.len();
+                (
+                    quote! {
+                        if let Some(obj) = json.as_object() {
+                            for unknown in obj.keys().filter(|k| ![#(#field_names),*].contains(&k.as_str())) {
+                                __errors.push(#krate::__private::format!("unknown field '{unknown}' in params"));
+                            }
+                        }
+                    },
+                    quote! {
+                        if arr.len() > #field_count {
+                            __errors.push(#krate::__private::format!("expected at most {} positional params, got {}", #field_count, arr.len()));
+                        }
+                    },
+                )
+            } else {
+                (quote! {}, quote! {})
+            };
+
+            quote! {
+              fn try_from_json(json: &#krate::__private::serde_json::Value) -> ::core::result::Result<Self, #krate::SeraphicError> {
+                    if let Some(arr) = json.as_array() {
+                        return Self::try_from_params_array(arr);
+                    }
+                    #init_errors
+                    #strict_object_check
                     #from_json_body
                     #create_self
               }
-            };
+              #[allow(unused_variables)]
+              fn try_from_params_array(arr: &[#krate::__private::serde_json::Value]) -> ::core::result::Result<Self, #krate::SeraphicError> {
+                    #init_errors
+                    #strict_array_check
+                    #from_array_body
+                    #create_self
+              }
+            }
+        }
+        // Each variant is a different operation, params are simply the serialized enum
+        syn::Data::Enum(_) => {
+            quote! {
+              fn try_from_json(json: &#krate::__private::serde_json::Value) -> ::core::result::Result<Self, #krate::SeraphicError> {
+                    Ok(#krate::__private::serde_json::from_value::<Self>(json.clone())?)
+              }
+            }
+        }
+        _ => {
+            panic!("cannot derive this on anything but a struct or an enum")
+        }
+    };
 
-            let method_name = quote! {
-                fn method()-> &'static str {
-                    #method
-                }
-            };
+    let method_name = quote! {
+        fn method()-> &'static str {
+            #method
+        }
+    };
 
-            let ns = opts.namespace;
-            let (ns_type, ns_var) = ns
-                .split_once(':')
-                .expect("expected namespace attribute to have a ':'");
+    let ns = opts.namespace;
+    let (ns_type, ns_var) = ns
+        .split_once(':')
+        .expect("expected namespace attribute to have a ':'");
 
-            let ns_type_id = format_ident!("{ns_type}");
-            let namespace = quote! {
-                fn namespace() -> Self::Namespace {
-                     Self::Namespace::try_from_str(#ns_var).unwrap()
+    let ns_type_id = format_ident!("{ns_type}");
+    let namespace = quote! {
+        fn namespace() -> Self::Namespace {
+             <Self::Namespace as #krate::RpcNamespace>::try_from_str(#ns_var).unwrap()
 
-                }
-            };
+        }
+    };
+
+    let (response_struct_name, should_impl) = match opts.response {
+        //if a response struct is passed in opt, it is assumed it alrady implements needed
+        //trait
+        Some(res) => (format_ident!("{}", res), false),
+        None => (format_ident!("{}Response", name_no_suffix), true),
+    };
+
+    let mut output = quote! {};
+    if should_impl {
+        let response_struct_id = match response_identity(&response_struct_name) {
+            Ok(id) => id,
+            Err(err) => return err.to_compile_error().into(),
+        };
+        output = quote! {
+            impl #krate::RpcResponse for #response_struct_name {
+                const IDENTITY: &str = #response_struct_id;
+            }
+        }
+    }
+    output = quote! {
+        #output
+        #debug
+        #eq
+        impl #krate::RpcRequest for #ident {
+            type Response = #response_struct_name;
+            type Namespace = #ns_type_id;
+            #strict_const
+            #from_json
+            #method_name
+            #namespace
+            #hash_params
+        }
+    };
 
-            let (response_struct_name, should_impl) = match opts.response {
-                //if a response struct is passed in opt, it is assumed it alrady implements needed
-                //trait
-                Some(res) => (format_ident!("{}", res), false),
-                None => (format_ident!("{}Response", name_no_suffix), true),
-            };
+    output.into()
+}
 
-            let mut output = quote! {};
-            let response_struct_id = format!("{response_struct_name}").to_lowercase();
-            if should_impl {
-                output = quote! {
-                    impl RpcResponse for #response_struct_name {
-                        const IDENTITY: &str = #response_struct_id;
-                    }
-                }
-            }
-            output = quote! {
-                #output
-                impl RpcRequest for #ident {
-                    type Response = #response_struct_name;
-                    type Namespace = #ns_type_id;
-                    #from_json
-                    #method_name
-                    #namespace
-                }
-            };
+/// `#[rpc_request(namespace = "NS:method")]` on its own, without `#[derive(RpcRequest)]`.
+/// Takes the same options as the derive's attribute
+#[proc_macro_attribute]
+pub fn rpc_request(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = proc_macro2::TokenStream::from(attr);
+    let annotated = proc_macro2::TokenStream::from(item.clone());
+    // the derive reads its options from the helper attribute, which the item keeps out of its
+    // own output so it can't clash with this macro's name
+    let derived = derive_rpc_req(quote! { #[rpc_request(#attr)] #annotated }.into());
+    let mut output = item;
+    output.extend(derived);
+    output
+}
 
-            output.into()
-        }
-        _ => {
-            panic!("cannot derive this on anything but a struct")
+#[derive(FromDeriveInput, Default)]
+#[darling(default, attributes(rpc_response))]
+struct ResponseOpts {
+    identity: Option<String>,
+}
+
+#[proc_macro_derive(RpcResponse, attributes(rpc_response))]
+pub fn derive_rpc_res(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input);
+    let opts = match ResponseOpts::from_derive_input(&input) {
+        Ok(opts) => opts,
+        Err(err) => return err.write_errors().into(),
+    };
+    let DeriveInput { ident, .. } = input;
+    let krate = crate_path();
+    let identity = match opts.identity.map_or_else(|| response_identity(&ident), Ok) {
+        Ok(identity) => identity,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let output = quote! {
+        impl #krate::RpcResponse for #ident {
+            const IDENTITY: &str = #identity;
         }
-    }
+    };
+    output.into()
 }
 
 #[proc_macro_derive(RequestWrapper)]
 pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input);
     let DeriveInput { ident, data, .. } = input;
+    let krate = crate_path();
     match data {
         Data::Enum(DataEnum { variants, .. }) => {
             let mut from_impls = quote! {};
             let mut into_req_body = quote! {};
             let mut from_req_body = quote! {
-                let e:Box<dyn std::error::Error + Send + Sync + 'static> = std::io::Error::other("Could not get Request object").into();
-                let mut ret = Err(e);
+                let mut ret = Err(#krate::SeraphicError::Protocol("Could not get Request object".into()));
             };
             for v in variants {
                 let id = v.ident;
@@ -149,13 +576,13 @@ pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
 
                 into_req_body = quote! {
                     #into_req_body
-                    Self::#id(r) => r.into_request(id).expect(#not_request),
+                    Self::#id(r) => #krate::RpcRequest::into_request(r, id).expect(#not_request),
                 };
 
                 from_req_body = quote! {
                     #from_req_body
                     if ret.is_err() {
-                        match #enum_typ::try_from_request(&req) {
+                        match <#enum_typ as #krate::RpcRequest>::try_from_request(&req) {
                             Ok(v) => return Ok(Self::#id(v)),
                             Err(e) => ret = Err(e),
                         }
@@ -173,7 +600,7 @@ pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
             }
 
             let into_req = quote! {
-                fn into_req(&self, id: impl ToString) -> seraphic::Request {
+                fn into_req(&self, id: impl Into<#krate::RequestId>) -> #krate::Request {
                     match self {
                         #into_req_body
                     }
@@ -181,7 +608,7 @@ pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
             };
 
             let from_req = quote! {
-                fn try_from_req(req: seraphic::Request) -> std::result::Result<Self,Box<dyn std::error::Error + Send + Sync + 'static>> {
+                fn try_from_req(req: #krate::Request) -> ::core::result::Result<Self, #krate::SeraphicError> {
                     #from_req_body
                     return ret;
                 }
@@ -189,7 +616,7 @@ pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
 
             let output = quote! {
                 #from_impls
-                impl seraphic::RequestWrapper for #ident {
+                impl #krate::RequestWrapper for #ident {
                     #into_req
                     #from_req
 
@@ -207,14 +634,12 @@ pub fn derive_req_wrapper(input: TokenStream) -> TokenStream {
 pub fn derive_res_wrapper(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input);
     let DeriveInput { ident, data, .. } = input;
+    let krate = crate_path();
     match data {
         Data::Enum(DataEnum { variants, .. }) => {
             let mut from_impls = quote! {};
             let mut into_res_body = quote! {};
-            let mut from_res_body = quote! {
-                let e:Box<dyn std::error::Error + Send + Sync + 'static> = std::io::Error::other("Could not get Response object").into();
-                let mut ret = Err(e);
-            };
+            let mut from_res_body = quote! {};
             for v in variants {
                 let id = v.ident;
                 let enum_typ = match v.fields {
@@ -230,13 +655,13 @@ pub fn derive_res_wrapper(input: TokenStream) -> TokenStream {
 
                 into_res_body = quote! {
                     #into_res_body
-                    Self::#id(r) => r.into_response(id).expect(#not_res),
+                    Self::#id(r) => #krate::msg::IdentifiedResponse::from_res(id, r).expect(#not_res),
                 };
 
                 from_res_body = quote! {
                     #from_res_body
-                    if ret.is_err() {
-                        ret = #enum_typ::try_from_response(&res).map(|maybe_ok|  maybe_ok.map(|ok| Self::#id(ok)));
+                    if res.id == <#enum_typ as #krate::RpcResponse>::IDENTITY {
+                        return <#enum_typ as #krate::RpcResponse>::try_from_response(&res.res).map(|maybe_ok| maybe_ok.map(|ok| Self::#id(ok)));
                     }
                 };
 
@@ -251,7 +676,7 @@ pub fn derive_res_wrapper(input: TokenStream) -> TokenStream {
             }
 
             let into_res = quote! {
-                fn into_res(&self, id: impl ToString) -> seraphic::IdentifiedResponse {
+                fn into_res(&self, id: impl Into<#krate::RequestId>) -> #krate::msg::IdentifiedResponse {
                     match self {
                         #into_res_body
                     }
@@ -259,15 +684,18 @@ pub fn derive_res_wrapper(input: TokenStream) -> TokenStream {
             };
 
             let from_res = quote! {
-                fn try_from_res(res: seraphic::IdentifiedResponse) -> std::result::Result<std::result::Result<Self, seraphic::error::Error>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+                fn try_from_res(res: #krate::msg::IdentifiedResponse) -> ::core::result::Result<::core::result::Result<Self, #krate::error::Error>, #krate::SeraphicError> {
                     #from_res_body
-                    return ret;
+                    Err(#krate::SeraphicError::Protocol(#krate::__private::format!(
+                        "Could not get Response object, unknown identity: {}",
+                        res.id
+                    )))
                 }
             };
 
             let output = quote! {
                 #from_impls
-                impl ResponseWrapper for #ident {
+                impl #krate::ResponseWrapper for #ident {
                     #into_res
                     #from_res
 
@@ -287,10 +715,18 @@ struct NamespaceOpts {
     separator: Option<String>,
 }
 
+#[derive(FromVariant, Default)]
+#[darling(default, attributes(namespace))]
+struct NamespaceVariantOpts {
+    // other names `try_from_str` accepts for this variant
+    aliases: Vec<syn::LitStr>,
+}
+
 #[proc_macro_derive(RpcNamespace, attributes(namespace))]
 pub fn derive_namespace(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input);
     let opts = NamespaceOpts::from_derive_input(&input).expect("Wrong options");
+    let krate = crate_path();
     let separator = opts.separator.unwrap_or("_".to_string());
     let separator = quote! {const SEPARATOR: &str = #separator;};
 
@@ -301,6 +737,9 @@ pub fn derive_namespace(input: TokenStream) -> TokenStream {
             let mut as_ref_body = quote! {};
             let mut my_str_consts = quote! {};
             for v in variants {
+                let aliases = NamespaceVariantOpts::from_variant(&v)
+                    .expect("Wrong options")
+                    .aliases;
                 let id = v.ident;
                 let id_str = format!("{id}");
                 let const_id = format_ident!("{}", id_str.to_uppercase());
@@ -311,7 +750,7 @@ pub fn derive_namespace(input: TokenStream) -> TokenStream {
                 };
                 from_str_body = quote! {
                     #from_str_body
-                    Self::#const_id => Some(Self::#id),
+                    Self::#const_id #(| #aliases)* => Some(Self::#id),
                 };
                 as_ref_body = quote! {
                     #as_ref_body
@@ -340,7 +779,7 @@ pub fn derive_namespace(input: TokenStream) -> TokenStream {
                 impl #ident {
                     #my_str_consts
                 }
-                impl RpcNamespace for #ident {
+                impl #krate::RpcNamespace for #ident {
                  #separator
                     #as_str
                     #try_from
@@ -0,0 +1,52 @@
+use rpc::derive::{RequestWrapper, ResponseWrapper, RpcNamespace, RpcRequest, RpcResponse};
+use serde::{Deserialize, Serialize};
+
+#[derive(RpcNamespace, Clone, Copy, PartialEq, Eq)]
+pub enum Ns {
+    Renamed,
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "Ns:renamed")]
+pub struct PingRequest {
+    n: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PingResponse {
+    n: u32,
+}
+
+#[derive(RpcResponse, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PongResponse {}
+
+#[derive(Debug, Clone, RequestWrapper, PartialEq)]
+pub enum Req {
+    Ping(PingRequest),
+}
+
+#[derive(Debug, Clone, ResponseWrapper, PartialEq)]
+pub enum Res {
+    Ping(PingResponse),
+    Pong(PongResponse),
+}
+
+#[test]
+fn derives_work_with_a_renamed_crate() {
+    use rpc::{RequestWrapper, ResponseWrapper};
+
+    let msg: rpc::Message<Req, Res> = Req::from(PingRequest { n: 1 }).into_message(0);
+    let json = serde_json::to_value(&msg).unwrap();
+    assert_eq!(json["method"], "renamed_ping");
+    assert_eq!(
+        serde_json::from_value::<rpc::Message<Req, Res>>(json).unwrap(),
+        msg
+    );
+
+    let msg: rpc::Message<Req, Res> = Res::from(PongResponse {}).into_message(0);
+    let json = serde_json::to_value(&msg).unwrap();
+    assert_eq!(
+        serde_json::from_value::<rpc::Message<Req, Res>>(json).unwrap(),
+        msg
+    );
+}
@@ -0,0 +1,96 @@
+//! Alternative ways of framing messages on a stream
+use crate::packet::{self, PacketBuffer};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{BufRead, ErrorKind};
+
+pub trait Codec {
+    fn encode<T>(msg: &T) -> std::io::Result<Vec<u8>>
+    where
+        T: Serialize;
+
+    /// Reads the next message, returning `None` once `reader` is exhausted
+    fn decode<T>(reader: &mut impl BufRead) -> std::io::Result<Option<T>>
+    where
+        T: DeserializeOwned;
+}
+
+/// The same framing `TcpPacket` uses: a little endian `u32` length followed by the JSON payload
+pub struct LengthDelimitedCodec;
+
+impl Codec for LengthDelimitedCodec {
+    fn encode<T>(msg: &T) -> std::io::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        packet::frame(&serde_json::to_vec(msg)?)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "message is too large"))
+    }
+
+    /// `None` only if `reader` ends between packets, ending part way through one is an error
+    fn decode<T>(reader: &mut impl BufRead) -> std::io::Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let mut buffer = PacketBuffer::new();
+        let payload = loop {
+            if let Some(payload) = buffer.next_payload() {
+                break payload;
+            }
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if available.is_empty() {
+                if buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "stream ended in the middle of a packet",
+                ));
+            }
+            // only take this packet's bytes, the rest belong to the next call
+            let take = available.len().min(buffer.missing());
+            buffer.extend(&available[..take]);
+            reader.consume(take);
+        };
+        serde_json::from_slice(&payload)
+            .map(Some)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+    }
+}
+
+/// One JSON value per line, as used by many JSON-RPC implementations in other languages
+pub struct NewlineDelimitedCodec;
+
+impl Codec for NewlineDelimitedCodec {
+    fn encode<T>(msg: &T) -> std::io::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        // compact JSON never contains a raw newline
+        let mut buffer = serde_json::to_vec(msg)?;
+        buffer.push(b'\n');
+        Ok(buffer)
+    }
+
+    fn decode<T>(reader: &mut impl BufRead) -> std::io::Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        serde_json::from_str(&line)
+            .map(Some)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+    }
+}
@@ -1,7 +1,97 @@
 use crate::{Message, RequestWrapper, ResponseWrapper};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::{fmt, time::Duration};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+/// A plain, heap allocated error message.
+/// Used in place of `std::io::Error` so the message types don't depend on `std`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationError(pub String);
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl core::error::Error for AllocationError {}
+
+impl From<String> for AllocationError {
+    fn from(str: String) -> Self {
+        Self(str)
+    }
+}
+
+impl From<&str> for AllocationError {
+    fn from(str: &str) -> Self {
+        Self(str.to_string())
+    }
+}
+
+/// Everything that can go wrong in seraphic, so callers can branch on the kind of failure
+#[derive(Debug)]
+pub enum SeraphicError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// A JSON-RPC error, such as invalid params or an unknown method
+    Rpc(Error),
+    /// The peer sent something that does not fit the protocol, such as a response with the wrong identity
+    Protocol(String),
+    Disconnected,
+    /// The caller's cancellation future completed first, see `ClientConnection::initialize_until`
+    Aborted,
+}
+
+impl fmt::Display for SeraphicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(err) => write!(f, "io error: {err}"),
+            Self::Json(err) => write!(f, "json error: {err}"),
+            Self::Rpc(err) => write!(f, "rpc error: {err}"),
+            Self::Protocol(msg) => write!(f, "protocol error: {msg}"),
+            Self::Disconnected => f.write_str("disconnected"),
+            Self::Aborted => f.write_str("aborted"),
+        }
+    }
+}
+
+impl core::error::Error for SeraphicError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Self::Io(err) => Some(err),
+            Self::Json(err) => Some(err),
+            Self::Rpc(err) => Some(err),
+            Self::Protocol(_) | Self::Disconnected | Self::Aborted => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for SeraphicError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SeraphicError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<Error> for SeraphicError {
+    fn from(err: Error) -> Self {
+        Self::Rpc(err)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Error {
     /// A Number that indicates the error type that occurred.
@@ -13,43 +103,352 @@ pub struct Error {
     /// A Primitive or Structured value that contains additional information about the error.
     /// This may be omitted.
     /// The value of this member is defined by the Server (e.g. detailed error information, nested errors etc.).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub data: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Keeps the io error's message, with the name of its kind in `data`.
+/// The peer going away maps to `Disconnect` and timeouts map to `Timeout`, so callers can tell
+/// them apart from failures on our side
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        use std::io::ErrorKind as Kind;
+        let code = match err.kind() {
+            Kind::ConnectionReset
+            | Kind::ConnectionAborted
+            | Kind::BrokenPipe
+            | Kind::UnexpectedEof => ErrorCode::Disconnect,
+            Kind::TimedOut | Kind::WouldBlock => ErrorCode::Timeout,
+            _ => ErrorCode::InternalError,
+        };
+        Self {
+            code,
+            message: err.to_string(),
+            data: Some(json!({ "kind": format!("{:?}", err.kind()) })),
+        }
+    }
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// `data` holds `{"method": method}`
+    pub fn method_not_found(method: &str) -> Self {
+        Self {
+            code: ErrorCode::MethodNotFound,
+            message: format!("method not found: {method}"),
+            data: Some(json!({ "method": method })),
+        }
+    }
+
+    pub fn invalid_params(detail: impl fmt::Display) -> Self {
+        Self::new(
+            ErrorCode::InvalidParams,
+            format!("invalid params: {detail}"),
+        )
+    }
+
+    pub fn parse_error(detail: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::ParseError, format!("parse error: {detail}"))
+    }
+
+    pub fn invalid_request(detail: impl fmt::Display) -> Self {
+        Self::new(
+            ErrorCode::InvalidRequest,
+            format!("invalid request: {detail}"),
+        )
+    }
+
+    pub fn internal(detail: impl fmt::Display) -> Self {
+        Self::new(
+            ErrorCode::InternalError,
+            format!("internal error: {detail}"),
+        )
+    }
+
+    /// Prepends `msg` to the message, keeping the code. Each previous message is appended to
+    /// `data.trace`
+    pub fn context(mut self, msg: impl Into<String>) -> Self {
+        let message = format!("{} -> {}", msg.into(), self.message);
+        let previous = core::mem::replace(&mut self.message, message);
+        let mut data = self.take_data_object();
+        match data.get_mut("trace") {
+            Some(serde_json::Value::Array(trace)) => trace.push(previous.into()),
+            _ => {
+                data.insert("trace".to_string(), json!([previous]));
+            }
+        }
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Marks the failure as retryable after `delay`, whatever the code.
+    /// Sets `data.seraphic` to `{"retryable": true, "retry_after_ms": n}`, keeping other data
+    pub fn retryable_after(self, delay: Duration) -> Self {
+        let retry_after_ms = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+        self.with_retry_meta(json!({ "retryable": true, "retry_after_ms": retry_after_ms }))
+    }
+
+    /// Marks the failure as one that will happen again if the request is retried
+    pub fn permanent(self) -> Self {
+        self.with_retry_meta(json!({ "retryable": false }))
+    }
+
+    /// Reads back what `retryable_after` or `permanent` set.
+    /// `None` if there is no `data.seraphic` or it is not in that shape
+    pub fn retry_hint(&self) -> Option<RetryHint> {
+        let meta = self.data.as_ref()?.get(SERAPHIC_DATA_KEY)?;
+        let retryable = meta.get("retryable")?.as_bool()?;
+        let retry_after = meta
+            .get("retry_after_ms")
+            .and_then(serde_json::Value::as_u64)
+            .map(Duration::from_millis);
+        Some(RetryHint {
+            retryable,
+            retry_after,
+        })
+    }
+
+    fn with_retry_meta(mut self, meta: serde_json::Value) -> Self {
+        let mut data = self.take_data_object();
+        data.insert(SERAPHIC_DATA_KEY.to_string(), meta);
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Non-object data is kept under `"data"`
+    fn take_data_object(&mut self) -> serde_json::Map<String, serde_json::Value> {
+        match self.data.take() {
+            Some(serde_json::Value::Object(obj)) => obj,
+            Some(other) => {
+                let mut obj = serde_json::Map::new();
+                obj.insert("data".to_string(), other);
+                obj
+            }
+            None => serde_json::Map::new(),
+        }
+    }
+
+    /// An error with an application defined `code`
+    pub fn app(code: i64, message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::from_code(code), message)
+    }
+
+    /// Only fails if `data` fails to serialize
+    pub fn with_data<T>(mut self, data: T) -> Result<Self, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        self.data = Some(serde_json::to_value(data)?);
+        Ok(self)
+    }
+
+    /// `None` if there is no data
+    pub fn data_as<T>(&self) -> Result<Option<T>, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.data
+            .as_ref()
+            .map(|data| T::deserialize(data))
+            .transpose()
+    }
+}
+
+/// The key in `Error::data` holding metadata this crate understands
+const SERAPHIC_DATA_KEY: &str = "seraphic";
+
+/// Whether a failed request is worth sending again, see `Error::retryable_after`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryHint {
+    pub retryable: bool,
+    pub retry_after: Option<Duration>,
+}
+
+/// Serialized as its integer code, as JSON-RPC requires.
+/// Codes are compared by value, so `Custom(-32601)` equals `MethodNotFound`
+#[derive(Debug, Clone, Copy)]
 #[non_exhaustive]
 pub enum ErrorCode {
     // Defined by JSON RPC:
-    ParseError = -32700,
-    InvalidRequest = -32600,
-    MethodNotFound = -32601,
-    InvalidParams = -32602,
-    InternalError = -32603,
-    ServerErrorStart = -32099,
-    ServerErrorEnd = -32000,
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    /// Reserved for implementation defined server errors, from `SERVER_ERROR_START` to
+    /// `SERVER_ERROR_END`
+    ServerError(i64),
+
+    Disconnect,
+    Timeout,
+    /// Given up on by our side, like a handshake cancelled with `initialize_until`
+    Aborted,
+    /// Any other code. JSON-RPC reserves -32768 to -32000 for itself, everything else is free
+    /// for applications to use
+    Custom(i64),
+}
+
+impl ErrorCode {
+    pub const SERVER_ERROR_START: i64 = -32099;
+    pub const SERVER_ERROR_END: i64 = -32000;
+
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::ParseError => -32700,
+            Self::InvalidRequest => -32600,
+            Self::MethodNotFound => -32601,
+            Self::InvalidParams => -32602,
+            Self::InternalError => -32603,
+            Self::ServerError(code) => *code,
+            Self::Disconnect => -29900,
+            Self::Timeout => -29901,
+            Self::Aborted => -29902,
+            Self::Custom(code) => *code,
+        }
+    }
+
+    /// The request itself was bad: it could not be parsed, was not a valid request, named an
+    /// unknown method or had the wrong params. Sending it again will fail again
+    pub fn is_protocol_error(&self) -> bool {
+        matches!(
+            Self::from_code(self.code()),
+            Self::ParseError | Self::InvalidRequest | Self::MethodNotFound | Self::InvalidParams
+        )
+    }
+
+    /// In the -32099 to -32000 range JSON-RPC reserves for implementation defined server errors
+    pub fn is_server_error(&self) -> bool {
+        (Self::SERVER_ERROR_START..=Self::SERVER_ERROR_END).contains(&self.code())
+    }
+
+    /// The connection failed rather than the request, `Disconnect` or `Timeout`
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            Self::from_code(self.code()),
+            Self::Disconnect | Self::Timeout
+        )
+    }
+
+    /// Whether sending the same request again could succeed. Only transport failures are
+    pub fn is_retryable(&self) -> bool {
+        self.is_transport()
+    }
+
+    /// The named variant for `code` if there is one, `ServerError(code)` inside the server error
+    /// range, otherwise `Custom(code)`
+    pub fn from_code(code: i64) -> Self {
+        match code {
+            -32700 => Self::ParseError,
+            -32600 => Self::InvalidRequest,
+            -32601 => Self::MethodNotFound,
+            -32602 => Self::InvalidParams,
+            -32603 => Self::InternalError,
+            Self::SERVER_ERROR_START..=Self::SERVER_ERROR_END => Self::ServerError(code),
+            -29900 => Self::Disconnect,
+            -29901 => Self::Timeout,
+            -29902 => Self::Aborted,
+            other => Self::Custom(other),
+        }
+    }
+}
+
+impl PartialEq for ErrorCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for ErrorCode {}
+
+impl core::hash::Hash for ErrorCode {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.code().hash(state)
+    }
+}
+
+impl From<i64> for ErrorCode {
+    fn from(code: i64) -> Self {
+        Self::from_code(code)
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_i64(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        i64::deserialize(deserializer).map(Self::from_code)
+    }
+}
 
-    Disconnect = -29900,
+/// Adds context to the error of a `Result` as it is propagated, see `Error::context`
+pub trait ErrorContext<T> {
+    fn context(self, msg: impl Into<String>) -> Result<T, Error>;
 }
 
+impl<T> ErrorContext<T> for Result<T, Error> {
+    fn context(self, msg: impl Into<String>) -> Result<T, Error> {
+        self.map_err(|err| err.context(msg))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> ErrorContext<T> for Result<T, std::io::Error> {
+    fn context(self, msg: impl Into<String>) -> Result<T, Error> {
+        self.map_err(|err| Error::from(err).context(msg))
+    }
+}
+
+/// How many bytes of an offending message errors echo back by default
+pub const DEFAULT_MAX_ERROR_PAYLOAD: usize = 1024;
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
-pub enum ErrorKind<'e> {
-    Other { str: &'e str, code: ErrorCode },
+pub enum ErrorKind {
+    Other { message: String, code: ErrorCode },
     Disconnect,
     Uninitialized(serde_json::Value),
 }
 
-impl<'e> Into<Error> for ErrorKind<'e> {
-    fn into(self) -> Error {
-        let (code, message, data) = match self {
-            Self::Other { str, code } => (code, str, None),
-            Self::Disconnect => (ErrorCode::Disconnect, "disconnected channel", None),
-            Self::Uninitialized(json) => (
-                ErrorCode::ServerErrorStart,
-                "uninitialized channel",
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        let (code, message, data) = match kind {
+            ErrorKind::Other { message, code } => (code, message, None),
+            ErrorKind::Disconnect => (
+                ErrorCode::Disconnect,
+                "disconnected channel".to_string(),
+                None,
+            ),
+            ErrorKind::Uninitialized(json) => (
+                ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START),
+                "uninitialized channel".to_string(),
                 Some(json),
             ),
         };
-        let message = message.to_string();
         Error {
             code,
             message,
@@ -58,18 +457,40 @@ impl<'e> Into<Error> for ErrorKind<'e> {
     }
 }
 
-impl<'e> ErrorKind<'e> {
-    pub fn other(str: &'e str, code: ErrorCode) -> Self {
-        Self::Other { str, code }
+impl ErrorKind {
+    pub fn other(message: impl Into<String>, code: ErrorCode) -> Self {
+        Self::Other {
+            message: message.into(),
+            code,
+        }
+    }
+
+    /// Echoes at most `DEFAULT_MAX_ERROR_PAYLOAD` bytes of `msg`, see `uninitialized_with_limit`
+    pub fn uninitialized<Rq, Rs>(msg: &Message<Rq, Rs>) -> Self
+    where
+        Rq: RequestWrapper,
+        Rs: ResponseWrapper,
+    {
+        Self::uninitialized_with_limit(msg, DEFAULT_MAX_ERROR_PAYLOAD)
     }
 
-    pub fn uninitialized<Rq, Rs>(msg: &'e Message<Rq, Rs>) -> Self
+    /// If `msg` serializes to more than `max_bytes`, the data holds only its start as a string,
+    /// marked with `"truncated": true`
+    pub fn uninitialized_with_limit<Rq, Rs>(msg: &Message<Rq, Rs>, max_bytes: usize) -> Self
     where
         Rq: RequestWrapper,
         Rs: ResponseWrapper,
     {
-        let payload = serde_json::to_value(msg)
-            .unwrap_or_else(|e| json!(format!("malformed payload: {e:#?}")));
+        let payload = match serde_json::to_vec(msg) {
+            Ok(bytes) if bytes.len() > max_bytes => json!({
+                "truncated": true,
+                "size": bytes.len(),
+                "payload": String::from_utf8_lossy(&bytes[..max_bytes]),
+            }),
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .unwrap_or_else(|e| json!(format!("malformed payload: {e:#?}"))),
+            Err(e) => json!(format!("malformed payload: {e:#?}")),
+        };
         Self::Uninitialized(payload)
     }
 }
@@ -0,0 +1,391 @@
+//! JSON-RPC over HTTP/1.1, for networks that only let HTTP through.
+//! Every message is the body of a `POST /rpc`, and its answer is the body of the `200 OK`.
+//! With the `tower` feature, `serve` also answers plain JSON-RPC requests from other tooling
+use crate::{
+    error::{Error, DEFAULT_MAX_ERROR_PAYLOAD},
+    tokio::{
+        connection::initialize_answer,
+        server::{answer_initialize, InitializeAnswer},
+    },
+    Message, RequestId, RequestWrapper, Response, ResponseWrapper, RpcRequest, SeraphicError,
+};
+#[cfg(feature = "tower")]
+use crate::{tokio::server::ACCEPT_ERROR_BACKOFF, tower::Service, MainErr, Request};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    client::conn::http1::SendRequest,
+    header,
+    server::conn::http1,
+    service::service_fn,
+    Method, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use std::{marker::PhantomData, net::SocketAddr};
+#[cfg(feature = "tower")]
+use tokio::net::TcpListener;
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, oneshot},
+};
+
+pub const RPC_PATH: &str = "/rpc";
+
+/// The largest request body the server reads, the same cap as `quic::MAX_MESSAGE_SIZE`.
+/// Anything bigger is answered with `413 Payload Too Large`
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+type HttpResponse = hyper::Response<Full<Bytes>>;
+
+/// A message from the client, and where to send the body of its answer
+type Exchange<Rq, Rs> = (Message<Rq, Rs>, oneshot::Sender<Vec<u8>>);
+
+fn status(status: StatusCode) -> HttpResponse {
+    let mut res = HttpResponse::new(Full::default());
+    *res.status_mut() = status;
+    res
+}
+
+fn json(body: Vec<u8>) -> HttpResponse {
+    let mut res = HttpResponse::new(Full::new(Bytes::from(body)));
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    res
+}
+
+fn json_response(res: &impl serde::Serialize) -> HttpResponse {
+    json(serde_json::to_vec(res).expect("responses serialize"))
+}
+
+fn hyper_error(err: hyper::Error) -> SeraphicError {
+    SeraphicError::Io(std::io::Error::other(err))
+}
+
+/// The response to anything that isn't a `POST` to `RPC_PATH`
+fn check_route(req: &hyper::Request<Incoming>) -> Option<HttpResponse> {
+    if req.uri().path() != RPC_PATH {
+        return Some(status(StatusCode::NOT_FOUND));
+    }
+    if req.method() != Method::POST {
+        return Some(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    None
+}
+
+/// The body of `req`, or the `413 Payload Too Large` to answer if it is over `MAX_MESSAGE_SIZE`
+async fn read_body(
+    req: hyper::Request<Incoming>,
+) -> Result<Result<Bytes, HttpResponse>, hyper::Error> {
+    match Limited::new(req.into_body(), MAX_MESSAGE_SIZE)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok(Ok(body.to_bytes())),
+        Err(err) => match err.downcast::<hyper::Error>() {
+            Ok(err) => Err(*err),
+            // the only other error `Limited` has is the body going over the limit
+            Err(_) => Ok(Err(status(StatusCode::PAYLOAD_TOO_LARGE))),
+        },
+    }
+}
+
+async fn handle<Rq, Rs>(
+    req: hyper::Request<Incoming>,
+    incoming: mpsc::Sender<Exchange<Rq, Rs>>,
+) -> Result<HttpResponse, hyper::Error>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    if let Some(res) = check_route(&req) {
+        return Ok(res);
+    }
+    let body = match read_body(req).await? {
+        Ok(body) => body,
+        Err(res) => return Ok(res),
+    };
+    let msg = match serde_json::from_slice::<Message<Rq, Rs>>(&body) {
+        Ok(msg) => msg,
+        Err(err) => {
+            // same as the tcp transport, valid JSON just isn't a message we understand
+            let err = match serde_json::from_slice::<serde_json::Value>(&body) {
+                Ok(_) => Error::invalid_request(&err),
+                Err(_) => Error::parse_error(&err),
+            };
+            return Ok(json_response(&Response::parse_error_null_id(err)));
+        }
+    };
+
+    let (reply, answer) = oneshot::channel();
+    if incoming.send((msg, reply)).await.is_err() {
+        return Ok(status(StatusCode::SERVICE_UNAVAILABLE));
+    }
+    Ok(match answer.await {
+        Ok(body) => json(body),
+        // the handler moved on without answering, as it would for a notification
+        Err(_) => status(StatusCode::NO_CONTENT),
+    })
+}
+
+/// The server side of an HTTP connection, used like `tokio::AsyncServerConnection`.
+/// Each message from `recv` is answered by at most one `send`
+pub struct HttpServerConnection<Rq, Rs> {
+    pub addr: SocketAddr,
+    incoming: mpsc::Receiver<Exchange<Rq, Rs>>,
+    reply: Option<oneshot::Sender<Vec<u8>>>,
+}
+
+impl<Rq, Rs> HttpServerConnection<Rq, Rs>
+where
+    Rq: RequestWrapper + Send + 'static,
+    Rs: ResponseWrapper + Send + 'static,
+{
+    /// Serves HTTP on `stream` from a background task
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        let addr = stream.peer_addr()?;
+        let (tx, incoming) = mpsc::channel(1);
+        let service = service_fn(move |req| handle(req, tx.clone()));
+        tokio::spawn(async move {
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("http connection with {addr} failed: {err}");
+            }
+        });
+        Ok(Self {
+            addr,
+            incoming,
+            reply: None,
+        })
+    }
+
+    /// Waits for the next message from the client. Returns `None` once the client has disconnected.
+    /// If the previous message was not answered, the client gets an empty `204 No Content`
+    pub async fn recv(&mut self) -> Option<Message<Rq, Rs>> {
+        self.reply = None;
+        let (msg, reply) = self.incoming.recv().await?;
+        self.reply = Some(reply);
+        Some(msg)
+    }
+
+    /// Answers the last message returned by `recv`
+    pub fn send(&mut self, msg: &Message<Rq, Rs>) -> Result<(), SeraphicError> {
+        self.reply(msg)
+    }
+
+    fn reply(&mut self, typ: &impl serde::Serialize) -> Result<(), SeraphicError> {
+        let reply = self.reply.take().ok_or_else(|| {
+            SeraphicError::Protocol("there is no received message left to answer".to_string())
+        })?;
+        reply
+            .send(serde_json::to_vec(typ)?)
+            .map_err(|_| SeraphicError::Disconnected)
+    }
+
+    /// Server side of the handshake, like `AsyncServerConnection::initialize`: waits for the
+    /// client to post `I`, answering it with `response`.
+    /// Any other message posted first is answered with an uninitialized error
+    pub async fn initialize<I>(&mut self, response: I::Response) -> Result<I, SeraphicError>
+    where
+        I: RpcRequest,
+    {
+        loop {
+            let msg = self.recv().await.ok_or(SeraphicError::Disconnected)?;
+            match answer_initialize(&msg, &response, DEFAULT_MAX_ERROR_PAYLOAD)? {
+                InitializeAnswer::Initialized(init, res) => {
+                    self.reply(&res)?;
+                    return Ok(init);
+                }
+                InitializeAnswer::Uninitialized(err) => self.reply(&err)?,
+            }
+        }
+    }
+}
+
+/// The client side of an HTTP connection
+pub struct HttpClientConnection<Rq, Rs> {
+    sender: SendRequest<Full<Bytes>>,
+    host: String,
+    marker: PhantomData<(Rq, Rs)>,
+}
+
+impl<Rq, Rs> HttpClientConnection<Rq, Rs>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, SeraphicError> {
+        let stream = TcpStream::connect(addr).await?;
+        let host = stream.peer_addr()?.to_string();
+        let (sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(hyper_error)?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                tracing::debug!("http connection failed: {err}");
+            }
+        });
+        Ok(Self {
+            sender,
+            host,
+            marker: PhantomData,
+        })
+    }
+
+    /// Client side of the handshake: posts `req` with id 0 and returns the server's answer
+    pub async fn initialize<I>(
+        &mut self,
+        req: I,
+    ) -> Result<Result<I::Response, Error>, SeraphicError>
+    where
+        I: RpcRequest,
+        Rq: From<I>,
+    {
+        let id = RequestId::from(0);
+        let init = Message::Req {
+            id: id.clone(),
+            req: Rq::from(req),
+        };
+        let answer = self.send(&init).await?.ok_or_else(|| {
+            SeraphicError::Protocol("the server did not answer the initialize request".to_string())
+        })?;
+        initialize_answer::<I, _, _>(&id, answer)
+    }
+
+    /// Posts `msg` and waits for the answer.
+    /// `None` if the server answered with an empty `204 No Content`, as it does for notifications
+    pub async fn send(
+        &mut self,
+        msg: &Message<Rq, Rs>,
+    ) -> Result<Option<Message<Rq, Rs>>, SeraphicError> {
+        let req = hyper::Request::post(RPC_PATH)
+            .header(header::HOST, &self.host)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(msg)?)))
+            .map_err(|err| SeraphicError::Protocol(err.to_string()))?;
+        self.sender.ready().await.map_err(hyper_error)?;
+        let res = self.sender.send_request(req).await.map_err(hyper_error)?;
+        match res.status() {
+            StatusCode::OK => {}
+            StatusCode::NO_CONTENT => return Ok(None),
+            status => {
+                return Err(SeraphicError::Protocol(format!(
+                    "unexpected HTTP status: {status}"
+                )))
+            }
+        }
+        let body = res.into_body().collect().await.map_err(hyper_error)?;
+        Ok(Some(serde_json::from_slice(&body.to_bytes())?))
+    }
+}
+
+/// Answers every JSON-RPC request POSTed to `RPC_PATH` on `listener` with `service`, as
+/// `tower::serve` does for TCP clients, for curl and other JSON-RPC over HTTP tooling.
+/// Batches are answered with an array. Everything is a `200 OK`, except a body that is not
+/// JSON, which is a `400 Bad Request`, notifications, which get an empty `204 No Content`, and
+/// bodies over `MAX_MESSAGE_SIZE`, which get a `413 Payload Too Large`.
+/// Runs until dropped: a failed accept is logged, and accepting carries on after a short pause
+#[cfg(feature = "tower")]
+pub async fn serve<S>(listener: TcpListener, service: S) -> std::io::Result<()>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<MainErr>,
+{
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("failed to accept an http connection: {err}");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let service = service.clone();
+        let http = service_fn(move |req| answer(req, service.clone()));
+        tokio::spawn(async move {
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), http)
+                .await
+            {
+                tracing::debug!("http connection with {addr} failed: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(feature = "tower")]
+async fn answer<S>(
+    req: hyper::Request<Incoming>,
+    mut service: S,
+) -> Result<HttpResponse, hyper::Error>
+where
+    S: Service<Request, Response = Response>,
+    S::Error: Into<MainErr>,
+{
+    if let Some(res) = check_route(&req) {
+        return Ok(res);
+    }
+    let body = match read_body(req).await? {
+        Ok(body) => body,
+        Err(res) => return Ok(res),
+    };
+    let value = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(value) => value,
+        Err(err) => {
+            let mut res = json_response(&Response::parse_error_null_id(Error::parse_error(&err)));
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(res);
+        }
+    };
+    Ok(match value {
+        serde_json::Value::Array(batch) if !batch.is_empty() => {
+            let mut answers = vec![];
+            for value in batch {
+                answers.extend(call(&mut service, value).await);
+            }
+            if answers.is_empty() {
+                status(StatusCode::NO_CONTENT)
+            } else {
+                json_response(&answers)
+            }
+        }
+        value => match call(&mut service, value).await {
+            Some(res) => json_response(&res),
+            None => status(StatusCode::NO_CONTENT),
+        },
+    })
+}
+
+/// Runs one request of a POST. `None` if it was a notification, which is not answered
+#[cfg(feature = "tower")]
+async fn call<S>(service: &mut S, mut value: serde_json::Value) -> Option<Response>
+where
+    S: Service<Request, Response = Response>,
+    S::Error: Into<MainErr>,
+{
+    let mut notification = false;
+    if let serde_json::Value::Object(obj) = &mut value {
+        // the spec lets params be left out, and a missing id makes it a notification
+        obj.entry("params").or_insert(serde_json::Value::Null);
+        notification = !obj.contains_key("id");
+    }
+    let req = match serde_json::from_value::<Request>(value) {
+        Ok(req) => req,
+        Err(err) => return Some(Response::parse_error_null_id(Error::invalid_request(&err))),
+    };
+    let id = req.id.clone();
+    let ready: Result<(), MainErr> = std::future::poll_fn(|cx| service.poll_ready(cx))
+        .await
+        .map_err(Into::into);
+    let called = match ready {
+        Ok(()) => service.call(req).await.map_err(Into::into),
+        Err(err) => Err(err),
+    };
+    let res =
+        called.unwrap_or_else(|err| Response::from_error(id, crate::tower::into_rpc_error(err)));
+    (!notification).then_some(res)
+}
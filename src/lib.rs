@@ -1,16 +1,95 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+// lets code generated by seraphic-derive refer to `::seraphic` from inside this crate
+extern crate self as seraphic;
+
+#[cfg(feature = "std")]
+pub mod codec;
 pub mod error;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod msg;
+#[cfg(feature = "std")]
 pub mod packet;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "std")]
+pub mod testing;
 #[cfg(feature = "tokio")]
 pub mod tokio;
+#[cfg(feature = "tower")]
+pub mod tower;
 
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+};
 use error::Error;
-pub use msg::{IdentifiedResponse, Message, MessageId, Request, Response};
+pub use error::SeraphicError;
+use msg::IdentifiedResponse;
+pub use msg::{Message, MessageId, Request, RequestId, Response};
 pub use seraphic_derive as derive;
 use serde_json::json;
 
-type MainErr = Box<dyn std::error::Error + Send + Sync + 'static>;
-type MainResult<T> = std::result::Result<T, MainErr>;
+/// Prefer `SeraphicError`, which converts into this
+pub type MainErr = Box<dyn core::error::Error + Send + Sync + 'static>;
+pub type MainResult<T> = core::result::Result<T, MainErr>;
+
+/// Items used by code generated in `seraphic-derive`, not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::{format, string::String, vec::Vec};
+    pub use serde_json;
+
+    /// Reports every problem found while parsing params as one `InvalidParams` error
+    pub fn invalid_params(errors: Vec<String>) -> crate::SeraphicError {
+        let mut err = crate::error::Error::invalid_params(errors.join("; "));
+        err.data = Some(errors.into());
+        err.into()
+    }
+
+    /// FNV-1a, so `RpcRequest::hash_params` is the same in every process and without `std`
+    pub struct ParamsHasher(u64);
+
+    impl Default for ParamsHasher {
+        fn default() -> Self {
+            Self(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl core::hash::Hasher for ParamsHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::io::Write for ParamsHasher {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            core::hash::Hasher::write(self, buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Feeds the JSON form of `value` to `hasher`, without buffering it when `std` is available
+    pub fn hash_json<T: serde::Serialize + ?Sized>(hasher: &mut ParamsHasher, value: &T) {
+        #[cfg(feature = "std")]
+        let _ = serde_json::to_writer(hasher, value);
+        #[cfg(not(feature = "std"))]
+        core::hash::Hasher::write(hasher, &serde_json::to_vec(value).unwrap_or_default());
+    }
+}
 
 pub const JSONRPC_FIELD: &str = "2.0";
 pub trait RpcNamespace: PartialEq + Copy {
@@ -22,57 +101,51 @@ pub trait RpcNamespace: PartialEq + Copy {
 }
 
 pub trait RpcResponse:
-    std::fmt::Debug + Clone + serde::Serialize + for<'de> serde::Deserialize<'de> + PartialEq
+    core::fmt::Debug + Clone + serde::Serialize + for<'de> serde::Deserialize<'de> + PartialEq
 {
     const IDENTITY: &str;
 
-    fn try_from_response(res: &IdentifiedResponse) -> MainResult<Result<Self, Error>> {
-        if res.id.as_str() != Self::IDENTITY {
-            return Err(std::io::Error::other(format!(
-                "Identities do not match, expected: {} got: {}",
-                Self::IDENTITY,
-                res.id
-            ))
-            .into());
-        }
-        if let Some(e) = &res.res.error {
+    /// `Ok(Err(_))` if `res` holds an error
+    fn try_from_response(res: &Response) -> Result<Result<Self, Error>, SeraphicError> {
+        if let Some(e) = &res.error {
             return Ok(Err(e.clone()));
         }
         let empty_json = json!({});
-        let val = res.res.result.as_ref().unwrap_or(&empty_json);
+        let val = res.result.as_ref().unwrap_or(&empty_json);
 
-        let me: Self = serde_json::from_value(val.clone()).expect("failed to deserialize Response");
+        let me: Self = serde_json::from_value(val.clone())?;
 
         Ok(Ok(me))
     }
 
     /// Only fails if self fails to serialize
-    fn into_response(&self, id: impl ToString) -> MainResult<IdentifiedResponse> {
+    #[allow(clippy::wrong_self_convention)]
+    fn into_response(&self, id: impl Into<RequestId>) -> Result<Response, SeraphicError> {
         let result = serde_json::to_value(self)?;
-        let res = Response {
+        Ok(Response {
             jsonrpc: JSONRPC_FIELD.to_string(),
-            id: id.to_string(),
+            id: id.into(),
             result: Some(result),
             error: None,
-        };
-        Ok(IdentifiedResponse {
-            id: Self::IDENTITY.to_string(),
-            res,
         })
     }
 }
 
 pub trait RpcRequest:
-    std::fmt::Debug
+    core::fmt::Debug
     + Clone
     + serde::Serialize
     + for<'de> serde::Deserialize<'de>
-    + std::marker::Send
+    + core::marker::Send
     + 'static
     + PartialEq
 {
     type Response: RpcResponse;
     type Namespace: RpcNamespace;
+    /// When `false`, fields in `params` that `Self` does not know about are ignored, so older
+    /// servers keep accepting requests from newer clients.
+    /// When `true`, unknown fields are rejected. Set it with `#[rpc_request(strict_params)]`
+    const STRICT_PARAMS: bool = false;
     fn method() -> &'static str;
     fn namespace() -> Self::Namespace;
 
@@ -86,74 +159,112 @@ pub trait RpcRequest:
     }
 
     /// Only fails if self fails to serialize
-    fn into_request(&self, id: impl ToString) -> MainResult<Request> {
-        let params = serde_json::to_value(&self)?;
+    #[allow(clippy::wrong_self_convention)]
+    fn into_request(&self, id: impl Into<RequestId>) -> Result<Request, SeraphicError> {
+        let params = serde_json::to_value(self)?;
         Ok(Request {
             jsonrpc: JSONRPC_FIELD.to_string(),
             method: Self::namespace_method(),
             params,
-            id: id.to_string(),
+            id: id.into(),
         })
     }
-    fn try_from_request(req: &Request) -> MainResult<Self> {
-        if let Some((namespace_str, method_str)) = req.method.split_once(Self::Namespace::SEPARATOR)
-        {
-            let namespace = Self::Namespace::try_from_str(namespace_str).unwrap();
-            if namespace != Self::namespace() || method_str != Self::method() {
-                return Err(std::io::Error::other(format!("namespace & method do not match expected. Got namespace: {namespace_str} with method: {method_str} expected namespace: {} with method: {}",
-                    Self::namespace().as_str(), Self::method()
-                )).into());
-            }
+    /// `into_request`, but `None` gives the request a null id, for protocols that don't need one
+    #[allow(clippy::wrong_self_convention)]
+    fn into_request_opt(&self, id: Option<impl Into<RequestId>>) -> Result<Request, SeraphicError> {
+        self.into_request(id.map_or(RequestId::Null, Into::into))
+    }
 
-            return Self::try_from_json(&req.params);
+    /// Fails with `Error::method_not_found` if `req` is not for this method
+    fn try_from_request(req: &Request) -> Result<Self, SeraphicError> {
+        let matches = req
+            .method
+            .split_once(Self::Namespace::SEPARATOR)
+            .is_some_and(|(namespace_str, method_str)| {
+                Self::Namespace::try_from_str(namespace_str)
+                    .is_some_and(|namespace| namespace == Self::namespace())
+                    && method_str == Self::method()
+            });
+        if !matches {
+            return Err(Error::method_not_found(&req.method).into());
         }
-        Err(std::io::Error::other(format!(
-            "Request method: {} could not be split by separator: {}",
-            req.method,
-            Self::Namespace::SEPARATOR
-        ))
-        .into())
-    }
-    fn try_from_json(json: &serde_json::Value) -> MainResult<Self>
+        Self::try_from_json(&req.params)
+    }
+
+    /// Parses positional params (a JSON array), by default relying on serde's support for
+    /// deserializing structs from sequences
+    fn try_from_params_array(arr: &[serde_json::Value]) -> Result<Self, SeraphicError>
+    where
+        Self: Sized,
+    {
+        Ok(serde_json::from_value(serde_json::Value::Array(
+            arr.to_vec(),
+        ))?)
+    }
+
+    /// Implementations should dispatch to `try_from_params_array` when `json` is an array
+    fn try_from_json(json: &serde_json::Value) -> Result<Self, SeraphicError>
     where
         Self: Sized;
+
+    /// A hash of the params that is stable across processes, for spotting a request a client
+    /// has retried. The derive hashes field by field rather than the whole serialized request
+    fn hash_params(&self) -> u64 {
+        let mut hasher = __private::ParamsHasher::default();
+        __private::hash_json(&mut hasher, self);
+        core::hash::Hasher::finish(&hasher)
+    }
+}
+
+/// `let req: Request = (my_req, 42).into();`
+/// Panics if the request fails to serialize, as with `into_request`
+impl<R, Id> From<(R, Id)> for Request
+where
+    R: RpcRequest,
+    Id: Into<RequestId>,
+{
+    fn from((req, id): (R, Id)) -> Self {
+        req.into_request(id).expect("request failed to serialize")
+    }
 }
 
-pub trait ResponseWrapper: std::fmt::Debug + PartialEq {
-    fn into_message<Rq>(self, id: impl ToString) -> Message<Rq, Self>
+pub trait ResponseWrapper: core::fmt::Debug + PartialEq {
+    fn into_message<Rq>(self, id: impl Into<RequestId>) -> Message<Rq, Self>
     where
         Rq: RequestWrapper,
         Self: Sized,
     {
         Message::Res {
-            id: id.to_string(),
+            id: id.into(),
             res: self,
         }
     }
-    fn into_res(&self, id: impl ToString) -> IdentifiedResponse
+    #[allow(clippy::wrong_self_convention)]
+    fn into_res(&self, id: impl Into<RequestId>) -> IdentifiedResponse
     where
         Self: Sized;
-    fn try_from_res(res: IdentifiedResponse) -> MainResult<Result<Self, Error>>
+    fn try_from_res(res: IdentifiedResponse) -> Result<Result<Self, Error>, SeraphicError>
     where
         Self: Sized;
 }
 
-pub trait RequestWrapper: std::fmt::Debug + PartialEq {
-    fn into_message<Rs>(self, id: impl ToString) -> Message<Self, Rs>
+pub trait RequestWrapper: core::fmt::Debug + PartialEq {
+    fn into_message<Rs>(self, id: impl Into<RequestId>) -> Message<Self, Rs>
     where
         Rs: ResponseWrapper,
         Self: Sized,
     {
         Message::Req {
-            id: id.to_string(),
+            id: id.into(),
             req: self,
         }
     }
 
-    fn into_req(&self, id: impl ToString) -> Request
+    #[allow(clippy::wrong_self_convention)]
+    fn into_req(&self, id: impl Into<RequestId>) -> Request
     where
         Self: Sized;
-    fn try_from_req(req: Request) -> MainResult<Self>
+    fn try_from_req(req: Request) -> Result<Self, SeraphicError>
     where
         Self: Sized;
 }
@@ -1,10 +1,81 @@
 use crate::{
-    Error as RpcError, RequestWrapper, ResponseWrapper, RpcRequest, RpcResponse, JSONRPC_FIELD,
+    Error as RpcError, RequestWrapper, ResponseWrapper, RpcRequest, RpcResponse, SeraphicError,
+    JSONRPC_FIELD,
 };
+use alloc::{
+    borrow::Cow,
+    format,
+    string::{String, ToString},
+};
+use core::fmt;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
-pub type MessageId = String;
+/// An identifier established by the Client, JSON RPC allows it to be a String, Number, or NULL.
+/// Numbers are kept as sent, so ids above `i64::MAX` from other implementations survive too
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash, Default)]
+#[serde(untagged)]
+pub enum RequestId {
+    Num(serde_json::Number),
+    Str(String),
+    /// Also what a notification, which has no id, deserializes with
+    #[default]
+    Null,
+}
+
+impl RequestId {
+    pub fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            Self::Str(str) => Cow::Borrowed(str),
+            Self::Num(num) => Cow::Owned(num.to_string()),
+            Self::Null => Cow::Borrowed("null"),
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+impl From<String> for RequestId {
+    fn from(str: String) -> Self {
+        Self::Str(str)
+    }
+}
+
+impl From<&str> for RequestId {
+    fn from(str: &str) -> Self {
+        Self::Str(str.to_string())
+    }
+}
+
+impl From<serde_json::Number> for RequestId {
+    fn from(num: serde_json::Number) -> Self {
+        Self::Num(num)
+    }
+}
+
+impl From<&RequestId> for RequestId {
+    fn from(id: &RequestId) -> Self {
+        id.clone()
+    }
+}
+
+macro_rules! request_id_from_int {
+    ($($int:ty),*) => {
+        $(impl From<$int> for RequestId {
+            fn from(num: $int) -> Self {
+                Self::Num(num.into())
+            }
+        })*
+    };
+}
+
+request_id_from_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+pub type MessageId = RequestId;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Message<Rq, Rs> {
     Req { id: MessageId, req: Rq },
@@ -12,6 +83,14 @@ pub enum Message<Rq, Rs> {
     Err { id: MessageId, err: RpcError },
 }
 
+impl<Rq, Rs> Message<Rq, Rs> {
+    pub fn id(&self) -> &MessageId {
+        match self {
+            Self::Req { id, .. } | Self::Res { id, .. } | Self::Err { id, .. } => id,
+        }
+    }
+}
+
 impl<'de, Rq, Rs> Deserialize<'de> for Message<Rq, Rs>
 where
     Rq: RequestWrapper,
@@ -36,7 +115,7 @@ where
             return Ok(Self::Req { id, req });
         }
 
-        if let Ok(res) = serde_json::from_value::<IdentifiedResponse>(json) {
+        if let Ok(res) = serde_json::from_value::<IdentifiedResponse>(json.clone()) {
             let id = res.res.id.clone();
             match Rs::try_from_res(res).map_err(|err| {
                 serde::de::Error::custom(format!(
@@ -48,6 +127,16 @@ where
             }
         }
 
+        // Errors are serialized as plain Responses, see the Serialize impl
+        if let Ok(Response {
+            id,
+            error: Some(err),
+            ..
+        }) = serde_json::from_value::<Response>(json)
+        {
+            return Ok(Self::Err { id, err });
+        }
+
         Err(serde::de::Error::custom(
             "Failed to deserialize any Message variant",
         ))
@@ -89,17 +178,45 @@ pub struct Request {
     /// A Structured value that holds the parameter values to be used during the invocation of the method. This member MAY be omitted.
     pub params: serde_json::Value,
     /// An identifier established by the Client that MUST contain a String, Number, or NULL value if included. If it is not included it is assumed to be a notification. The value SHOULD normally not be Null [1] and Numbers SHOULD NOT contain fractional parts [2]
-    pub id: String,
+    /// A notification deserializes with `RequestId::Null`
+    #[serde(default)]
+    pub id: RequestId,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 /// Because structs implementing RpcResponse *could* have identical bodies, this allows them to be
 /// identified by string and serialized/deserialized that way.
+/// Only used for the wire format of `Message`, see `ResponseWrapper`
+#[doc(hidden)]
 pub struct IdentifiedResponse {
     pub id: String,
     pub res: Response,
 }
 
+impl IdentifiedResponse {
+    pub fn from_res<R: RpcResponse>(
+        id: impl Into<RequestId>,
+        res: &R,
+    ) -> Result<Self, SeraphicError> {
+        Ok(Self {
+            id: R::IDENTITY.to_string(),
+            res: res.into_response(id)?,
+        })
+    }
+
+    /// Fails with `SeraphicError::Protocol` if this is not a response for `R`
+    pub fn try_into_res<R: RpcResponse>(&self) -> Result<Result<R, RpcError>, SeraphicError> {
+        if self.id != R::IDENTITY {
+            return Err(SeraphicError::Protocol(format!(
+                "Identities do not match, expected: {} got: {}",
+                R::IDENTITY,
+                self.id
+            )));
+        }
+        R::try_from_response(&self.res)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Response {
     /// A String specifying the version of the JSON-RPC protocol. MUST be exactly "2.0".
@@ -108,35 +225,61 @@ pub struct Response {
     /// This member is REQUIRED on success.
     /// This member MUST NOT exist if there was an error invoking the method.
     /// The value of this member is determined by the method invoked on the Server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub result: Option<serde_json::Value>,
 
     /// This member is REQUIRED on error.
     /// This member MUST NOT exist if there was no error triggered during invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub error: Option<crate::error::Error>,
 
     /// This member is REQUIRED.
     /// It MUST be the same as the value of the id member in the Request Object.
     /// If there was an error in detecting the id in the Request object (e.g. Parse error/Invalid Request), it MUST be Null.
-    pub id: String,
+    pub id: RequestId,
 }
 
 impl Request {
-    pub fn from_req(id: impl ToString, req: impl RpcRequest) -> Self {
+    pub fn from_req(id: impl Into<RequestId>, req: impl RpcRequest) -> Self {
         req.into_request(id).unwrap()
     }
 }
 
 impl Response {
-    pub fn from_error(id: impl ToString, error: crate::error::Error) -> Self {
+    pub fn from_error(id: impl Into<RequestId>, error: crate::error::Error) -> Self {
         Self {
             jsonrpc: JSONRPC_FIELD.to_string(),
             result: None,
             error: Some(error),
-            id: id.to_string(),
+            id: id.into(),
+        }
+    }
+
+    /// For a parse error or invalid request, when the request's id could not be read.
+    /// The spec requires the id to be null in that case
+    pub fn parse_error_null_id(error: crate::error::Error) -> Self {
+        Self::from_error(RequestId::Null, error)
+    }
+
+    pub fn from_res(id: impl Into<RequestId>, res: impl RpcResponse) -> Self {
+        res.into_response(id).unwrap()
+    }
+
+    /// Panics if this is an error response
+    pub fn unwrap_result(self) -> Value {
+        match self.result {
+            Some(result) => result,
+            None => panic!("expected result, got error: {:?}", self.error),
         }
     }
 
-    pub fn from_res(id: impl ToString, res: impl RpcResponse) -> Self {
-        res.into_response(id).unwrap().res
+    /// Panics if this is not an error response
+    pub fn unwrap_error(self) -> RpcError {
+        self.error.expect("expected error, got result")
+    }
+
+    /// Deserializes the result, panicking if this is an error response
+    pub fn result_as<T: for<'de> Deserialize<'de>>(self) -> Result<T, SeraphicError> {
+        Ok(serde_json::from_value(self.unwrap_result())?)
     }
 }
@@ -1,7 +1,7 @@
-use crate::MainResult;
+use crate::SeraphicError;
 use serde::{Deserialize, Serialize};
 use std::{
-    io::{BufRead, ErrorKind, Write},
+    io::{BufRead, ErrorKind, Read, Write},
     marker::PhantomData,
 };
 
@@ -22,6 +22,15 @@ pub(crate) const fn header_size() -> usize {
     std::mem::size_of::<HeaderSize>() / std::mem::size_of::<u8>()
 }
 
+/// Prefixes `payload` with its length. `None` if the length doesn't fit in the header
+pub(crate) fn frame(payload: &[u8]) -> Option<Vec<u8>> {
+    let size = HeaderSize::try_from(payload.len()).ok()?;
+    let mut buffer = Vec::with_capacity(header_size() + payload.len());
+    buffer.extend_from_slice(&size.to_le_bytes());
+    buffer.extend_from_slice(payload);
+    Some(buffer)
+}
+
 impl<T> TcpPacket<T> {
     pub fn buffer(&self) -> &[u8] {
         &self.buffer
@@ -32,14 +41,14 @@ impl<T> TcpPacket<T>
 where
     T: Serialize + std::fmt::Debug + for<'de> Deserialize<'de>,
 {
-    pub fn try_into_inner(self) -> MainResult<T> {
+    pub fn try_into_inner(self) -> Result<T, SeraphicError> {
         let buf = &self.buffer[header_size()..];
-        let str = String::from_utf8_lossy(buf);
         serde_json::from_slice::<T>(buf).map_err(|err| {
-            std::io::Error::other(format!(
-                "error getting tcp packet inner from slice: {err:#?}\nbuffer: {str}"
-            ))
-            .into()
+            tracing::debug!(
+                "error getting tcp packet inner from slice: {err:#?}\nbuffer: {}",
+                String::from_utf8_lossy(buf)
+            );
+            err.into()
         })
     }
 }
@@ -50,17 +59,7 @@ where
 {
     fn from(r: &T) -> Self {
         let vec = serde_json::to_vec(r).expect("T will not work");
-
-        assert!(
-            vec.len() <= HeaderSize::MAX as usize,
-            "consider making the header size larger"
-        );
-
-        let size: u32 = vec.len() as u32;
-
-        let mut buffer = Vec::with_capacity(header_size() + vec.len());
-        buffer.extend_from_slice(&size.to_le_bytes());
-        buffer.extend_from_slice(&vec);
+        let buffer = frame(&vec).expect("consider making the header size larger");
         Self {
             marker: PhantomData,
             buffer,
@@ -90,9 +89,68 @@ impl<T> Serialize for TcpPacket<T> {
     }
 }
 
+/// Accumulates bytes read from a stream until a whole packet is available.
+/// Keeping partial reads here rather than on the stack of a read call is what makes
+/// `TcpPacket::async_read_buffered` cancel safe, and lets `TcpPacket::read_buffered` pick up
+/// where it left off on a nonblocking socket
+#[derive(Debug, Default)]
+pub struct PacketBuffer {
+    buffer: Vec<u8>,
+    /// When the first bytes of the packet being buffered were seen by a read with a frame
+    /// timeout, so giving up on a read and starting another doesn't restart the clock
+    pub(crate) frame_started: Option<std::time::Instant>,
+}
+
+impl PacketBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// True if no bytes of a partial packet are being held
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// How many more bytes the next packet needs before `next_payload` can return it,
+    /// counting the rest of its header if that hasn't arrived yet
+    pub(crate) fn missing(&self) -> usize {
+        match self.buffer.get(..header_size()) {
+            Some(header) => {
+                let header: [u8; header_size()] = header.try_into().expect("header is 4 bytes");
+                (header_size() + HeaderSize::from_le_bytes(header) as usize)
+                    .saturating_sub(self.buffer.len())
+            }
+            None => header_size() - self.buffer.len(),
+        }
+    }
+
+    /// Removes and returns the payload of the next complete packet, if one has been buffered
+    pub fn next_payload(&mut self) -> Option<Vec<u8>> {
+        let header: [u8; header_size()] = self.buffer.get(..header_size())?.try_into().ok()?;
+        let end = header_size() + HeaderSize::from_le_bytes(header) as usize;
+        if self.buffer.len() < end {
+            return None;
+        }
+        let payload = self.buffer[header_size()..end].to_vec();
+        self.buffer.drain(..end);
+        self.frame_started = None;
+        Some(payload)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum PacketRead<T> {
     Message(T),
+    /// A whole packet was read but its payload could not be deserialized into `T`.
+    /// The stream is still aligned on packet boundaries, so reading can continue
+    Malformed {
+        payload: Vec<u8>,
+        reason: String,
+    },
     Disconnected,
     Empty,
 }
@@ -101,6 +159,8 @@ impl<T> TcpPacket<T>
 where
     T: Serialize + std::fmt::Debug + for<'de> Deserialize<'de>,
 {
+    /// Reads one packet. `PacketRead::Empty` if `inp` would block, and
+    /// `PacketRead::Malformed` if the payload is not a `T`
     pub fn read(inp: &mut dyn BufRead) -> std::io::Result<PacketRead<T>> {
         let mut header = [0u8; header_size()];
         let mut buffer = [0u8; 1024].to_vec();
@@ -123,34 +183,71 @@ where
                     return Ok(PacketRead::Empty);
                 }
                 Err(err) => {
-                    return Err(std::io::Error::other(format!(
-                        "unexepect error when reading header: {err:#?}\nbuffer: {}",
-                        String::from_utf8_lossy(&buffer)
-                    )));
+                    return Err(std::io::Error::new(
+                        err.kind(),
+                        format!(
+                            "unexepect error when reading header: {err:#?}\nbuffer: {}",
+                            String::from_utf8_lossy(&buffer)
+                        ),
+                    ));
                 }
             }
         }
-        let size: usize = size.ok_or(std::io::Error::other("no content length"))?;
+        let size: usize = size.ok_or(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "no content length",
+        ))?;
         tracing::debug!("got payload size from header: {size}");
         buffer.resize(size, 0);
         match inp.read_exact(&mut buffer) {
-            Ok(_) => {
-                let typ = serde_json::from_slice::<T>(&buffer).map_err(|err| {
-                    std::io::Error::other(format!(
-                        "malformed payload: {}\nErr: {err:#?}",
-                        String::from_utf8_lossy(&buffer),
-                    ))
-                })?;
-                Ok(PacketRead::Message(typ))
-            }
-            Err(err) if err.kind() == ErrorKind::WouldBlock => {
-                return Ok(PacketRead::Empty);
-            }
-            Err(err) => {
-                return Err(std::io::Error::other(format!(
+            Ok(_) => Ok(match serde_json::from_slice::<T>(&buffer) {
+                Ok(typ) => PacketRead::Message(typ),
+                Err(err) => PacketRead::Malformed {
+                    payload: buffer,
+                    reason: err.to_string(),
+                },
+            }),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(PacketRead::Empty),
+            Err(err) => Err(std::io::Error::new(
+                err.kind(),
+                format!(
                     "unexepect error when reading payload: {err:#?}\nbuffer: {}",
                     String::from_utf8_lossy(&buffer)
-                )));
+                ),
+            )),
+        }
+    }
+
+    /// Like `read`, but bytes of a partial header or payload are kept in `buffer` when `inp`
+    /// would block, so a nonblocking socket can be read from again without losing data.
+    /// Payloads that fail to deserialize are returned as `PacketRead::Malformed` instead of an error
+    pub fn read_buffered(
+        inp: &mut dyn Read,
+        buffer: &mut PacketBuffer,
+    ) -> std::io::Result<PacketRead<T>> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            if let Some(payload) = buffer.next_payload() {
+                return Ok(match serde_json::from_slice::<T>(&payload) {
+                    Ok(typ) => PacketRead::Message(typ),
+                    Err(err) => PacketRead::Malformed {
+                        payload,
+                        reason: err.to_string(),
+                    },
+                });
+            }
+            match inp.read(&mut chunk) {
+                Ok(0) if buffer.is_empty() => return Ok(PacketRead::Disconnected),
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "disconnected in the middle of a packet",
+                    ))
+                }
+                Ok(n) => buffer.extend(&chunk[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(PacketRead::Empty),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
             }
         }
     }
@@ -0,0 +1,296 @@
+//! JSON-RPC over QUIC, with `quinn`. Every message is sent on its own unidirectional stream,
+//! so QUIC's stream framing takes the place of `TcpPacket`.
+//! Endpoints are configured with quinn directly, including their TLS certificates
+use crate::{
+    error::{Error, DEFAULT_MAX_ERROR_PAYLOAD},
+    tokio::{
+        connection::{initialize_answer, CHANNEL_CAPACITY},
+        server::{
+            answer_initialize, malformed_response, InitializeAnswer, DEFAULT_MAX_MALFORMED_PACKETS,
+        },
+    },
+    Message, RequestId, RequestWrapper, ResponseWrapper, RpcRequest, SeraphicError,
+};
+use futures_util::{stream::FuturesOrdered, StreamExt};
+pub use quinn;
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+};
+
+/// The largest message `recv` accepts
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How long a stream has to finish once the peer has opened it, unless set with `bind_with` or
+/// `connect_with`. A stream that doesn't is dropped, so the messages after it get through
+pub const DEFAULT_STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn quic_error(err: impl std::error::Error + Send + Sync + 'static) -> SeraphicError {
+    SeraphicError::Io(std::io::Error::other(err))
+}
+
+/// Accepts connections from `QuicConnection::connect`
+pub struct QuicServer<Rq, Rs> {
+    endpoint: quinn::Endpoint,
+    stream_timeout: Option<Duration>,
+    marker: PhantomData<(Rq, Rs)>,
+}
+
+impl<Rq, Rs> QuicServer<Rq, Rs>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    pub fn bind(config: quinn::ServerConfig, addr: SocketAddr) -> std::io::Result<Self> {
+        Self::bind_with(config, addr, Some(DEFAULT_STREAM_TIMEOUT))
+    }
+
+    /// Like `bind`, with the `DEFAULT_STREAM_TIMEOUT` of accepted connections replaced.
+    /// `None` waits forever
+    pub fn bind_with(
+        config: quinn::ServerConfig,
+        addr: SocketAddr,
+        stream_timeout: Option<Duration>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            endpoint: quinn::Endpoint::server(config, addr)?,
+            stream_timeout,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Waits for the next client to connect. Returns `None` once the server has been closed
+    pub async fn accept(&self) -> Result<Option<QuicConnection<Rq, Rs>>, SeraphicError> {
+        let Some(incoming) = self.endpoint.accept().await else {
+            return Ok(None);
+        };
+        let conn = incoming.await.map_err(quic_error)?;
+        Ok(Some(QuicConnection::new(conn, None, self.stream_timeout)))
+    }
+
+    /// Closes every connection and stops accepting
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"server closed");
+    }
+}
+
+/// Either end of a QUIC connection
+pub struct QuicConnection<Rq, Rs> {
+    conn: quinn::Connection,
+    /// A client's own endpoint, kept so it lives as long as the connection
+    _endpoint: Option<quinn::Endpoint>,
+    /// Payloads read by `reader`, in the order their streams were opened
+    payloads: Mutex<mpsc::Receiver<Result<Vec<u8>, SeraphicError>>>,
+    reader: JoinHandle<()>,
+    malformed_messages: AtomicUsize,
+    marker: PhantomData<(Rq, Rs)>,
+}
+
+impl<Rq, Rs> Drop for QuicConnection<Rq, Rs> {
+    fn drop(&mut self) {
+        // it holds on to `conn`, which would otherwise never close
+        self.reader.abort();
+    }
+}
+
+/// Reads every stream the peer opens at once, so a stream that is slow to finish doesn't hold
+/// up reading the ones opened after it
+async fn read_streams(
+    conn: quinn::Connection,
+    payloads: mpsc::Sender<Result<Vec<u8>, SeraphicError>>,
+    stream_timeout: Option<Duration>,
+) {
+    let mut reads = FuturesOrdered::new();
+    loop {
+        tokio::select! {
+            stream = conn.accept_uni() => match stream {
+                Ok(mut stream) => reads.push_back(async move {
+                    let read = stream.read_to_end(MAX_MESSAGE_SIZE);
+                    let read = match stream_timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, read).await.ok(),
+                        None => Some(read.await),
+                    };
+                    read.map(|read| read.map_err(quic_error))
+                }),
+                Err(
+                    quinn::ConnectionError::ApplicationClosed(_)
+                    | quinn::ConnectionError::ConnectionClosed(_)
+                    | quinn::ConnectionError::LocallyClosed,
+                ) => break,
+                Err(err) => {
+                    let _ = payloads.send(Err(quic_error(err))).await;
+                    return;
+                }
+            },
+            Some(payload) = reads.next() => {
+                let Some(payload) = payload else {
+                    tracing::warn!(
+                        "{} opened a stream that wasn't finished in time",
+                        conn.remote_address()
+                    );
+                    continue;
+                };
+                if payloads.send(payload).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    // what was finished before the connection closed is still delivered
+    while let Some(Some(Ok(payload))) = reads.next().await {
+        if payloads.send(Ok(payload)).await.is_err() {
+            return;
+        }
+    }
+}
+
+impl<Rq, Rs> QuicConnection<Rq, Rs>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    fn new(
+        conn: quinn::Connection,
+        endpoint: Option<quinn::Endpoint>,
+        stream_timeout: Option<Duration>,
+    ) -> Self {
+        let (sender, payloads) = mpsc::channel(CHANNEL_CAPACITY);
+        Self {
+            reader: tokio::spawn(read_streams(conn.clone(), sender, stream_timeout)),
+            payloads: Mutex::new(payloads),
+            conn,
+            _endpoint: endpoint,
+            malformed_messages: AtomicUsize::new(0),
+            marker: PhantomData,
+        }
+    }
+
+    /// Connects to a `QuicServer`. `server_name` is checked against the server's certificate
+    pub async fn connect(
+        config: quinn::ClientConfig,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Self, SeraphicError> {
+        Self::connect_with(config, addr, server_name, Some(DEFAULT_STREAM_TIMEOUT)).await
+    }
+
+    /// Like `connect`, with the `DEFAULT_STREAM_TIMEOUT` replaced. `None` waits forever
+    pub async fn connect_with(
+        config: quinn::ClientConfig,
+        addr: SocketAddr,
+        server_name: &str,
+        stream_timeout: Option<Duration>,
+    ) -> Result<Self, SeraphicError> {
+        let bind: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let mut endpoint = quinn::Endpoint::client(bind)?;
+        endpoint.set_default_client_config(config);
+        let conn = endpoint
+            .connect(addr, server_name)
+            .map_err(quic_error)?
+            .await
+            .map_err(quic_error)?;
+        Ok(Self::new(conn, Some(endpoint), stream_timeout))
+    }
+
+    pub fn remote_address(&self) -> SocketAddr {
+        self.conn.remote_address()
+    }
+
+    async fn write<T: serde::Serialize>(&self, typ: &T) -> Result<(), SeraphicError> {
+        let mut stream = self.conn.open_uni().await.map_err(quic_error)?;
+        stream
+            .write_all(&serde_json::to_vec(typ)?)
+            .await
+            .map_err(quic_error)?;
+        stream.finish().map_err(quic_error)?;
+        Ok(())
+    }
+
+    pub async fn send(&self, msg: &Message<Rq, Rs>) -> Result<(), SeraphicError> {
+        self.write(msg).await
+    }
+
+    /// Waits for the next message from the peer, in the order their streams were opened.
+    /// Returns `None` once the connection is closed.
+    /// Malformed messages are answered with an error response and skipped, as on TCP, until
+    /// there have been more than `DEFAULT_MAX_MALFORMED_PACKETS` of them
+    pub async fn recv(&self) -> Result<Option<Message<Rq, Rs>>, SeraphicError> {
+        loop {
+            let Some(payload) = self.payloads.lock().await.recv().await else {
+                return Ok(None);
+            };
+            let payload = payload?;
+            let reason = match serde_json::from_slice(&payload) {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(err) => err.to_string(),
+            };
+
+            let malformed = self.malformed_messages.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(
+                "{} sent malformed message #{malformed}: {reason}",
+                self.remote_address()
+            );
+            self.write(&malformed_response(&payload, reason)).await?;
+            if malformed > DEFAULT_MAX_MALFORMED_PACKETS {
+                return Err(SeraphicError::Protocol(format!(
+                    "{} sent too many malformed messages",
+                    self.remote_address()
+                )));
+            }
+        }
+    }
+
+    /// Client side of the handshake: sends `req` with id 0 on the connection's first stream and
+    /// waits for the server to answer it
+    pub async fn initialize<I>(&self, req: I) -> Result<Result<I::Response, Error>, SeraphicError>
+    where
+        I: RpcRequest,
+        Rq: From<I>,
+    {
+        let id = RequestId::from(0);
+        self.send(&Message::Req {
+            id: id.clone(),
+            req: Rq::from(req),
+        })
+        .await?;
+        let answer = self.recv().await?.ok_or(SeraphicError::Disconnected)?;
+        initialize_answer::<I, _, _>(&id, answer)
+    }
+
+    /// Server side of the handshake, like `AsyncServerConnection::initialize`: waits for the
+    /// client to send `I`, answering it with `response`.
+    /// Any other message received first is answered with an uninitialized error
+    pub async fn accept_initialize<I>(&self, response: I::Response) -> Result<I, SeraphicError>
+    where
+        I: RpcRequest,
+    {
+        loop {
+            let msg = self.recv().await?.ok_or(SeraphicError::Disconnected)?;
+            match answer_initialize(&msg, &response, DEFAULT_MAX_ERROR_PAYLOAD)? {
+                InitializeAnswer::Initialized(init, res) => {
+                    self.write(&res).await?;
+                    return Ok(init);
+                }
+                InitializeAnswer::Uninitialized(err) => self.write(&err).await?,
+            }
+        }
+    }
+
+    pub fn close(&self) {
+        self.conn.close(0u32.into(), b"closed");
+    }
+}
@@ -0,0 +1,168 @@
+//! Helpers for testing your own protocol types
+use crate::{
+    packet::{header_size, PacketRead, TcpPacket},
+    Message, RequestWrapper, ResponseWrapper,
+};
+
+/// Writes `msg` as a packet, reads it back and asserts nothing changed on the way.
+/// Panics if the header does not match the payload length or if the message does not survive
+/// serialization
+pub fn assert_roundtrip<Rq, Rs>(msg: &Message<Rq, Rs>)
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    let mut bytes = vec![];
+    TcpPacket::write(&mut bytes, msg).expect("failed to write packet");
+
+    let header: [u8; header_size()] = bytes
+        .get(..header_size())
+        .and_then(|h| h.try_into().ok())
+        .expect("packet is shorter than its header");
+    let payload_len = u32::from_le_bytes(header) as usize;
+    assert_eq!(
+        payload_len,
+        bytes.len() - header_size(),
+        "packet header does not match payload length"
+    );
+    assert_eq!(
+        bytes,
+        TcpPacket::from(msg).buffer(),
+        "written packet does not match TcpPacket::from"
+    );
+
+    match TcpPacket::<Message<Rq, Rs>>::read(&mut bytes.as_slice()) {
+        Ok(PacketRead::Message(read)) => assert_eq!(&read, msg, "message changed in roundtrip"),
+        Ok(other) => panic!("expected a message, got: {other:#?}"),
+        Err(err) => panic!(
+            "failed to read back message: {err}\npayload: {}",
+            String::from_utf8_lossy(&bytes[header_size()..])
+        ),
+    }
+}
+
+/// How many bytes `MockClientConnection::pair` buffers in each direction
+#[cfg(feature = "tokio")]
+pub const MOCK_CAPACITY: usize = 64 * 1024;
+
+/// The client end of an `AsyncServerConnection` that is not owned by a `Server`, for testing a
+/// `ServerConnectionHandler` on its own. The two ends are joined in memory, with no socket
+#[cfg(feature = "tokio")]
+pub struct MockClientConnection<Rq, Rs> {
+    stream: tokio::io::DuplexStream,
+    buffer: crate::packet::PacketBuffer,
+    marker: core::marker::PhantomData<(Rq, Rs)>,
+}
+
+#[cfg(feature = "tokio")]
+impl<Rq, Rs> MockClientConnection<Rq, Rs>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    /// Pass the connection to the handler under test and drive it with the mock.
+    /// The connection's `addr` is `127.0.0.1:0`
+    pub fn pair() -> (Self, crate::tokio::AsyncServerConnection<Rq, Rs>) {
+        Self::pair_with_capacity(MOCK_CAPACITY)
+    }
+
+    /// Like `pair`, buffering at most `capacity` bytes each way. Writes wait once it is full,
+    /// as they would on a client that stopped reading
+    pub fn pair_with_capacity(
+        capacity: usize,
+    ) -> (Self, crate::tokio::AsyncServerConnection<Rq, Rs>) {
+        let (stream, server) = tokio::io::duplex(capacity);
+        let (reader, writer) = tokio::io::split(server);
+        let mock = Self {
+            stream,
+            buffer: Default::default(),
+            marker: core::marker::PhantomData,
+        };
+        let conn =
+            crate::tokio::AsyncServerConnection::standalone(Box::new(reader), Box::new(writer));
+        (mock, conn)
+    }
+
+    /// Simulates `msg` arriving from the client
+    pub async fn inject(&mut self, msg: &Message<Rq, Rs>) -> std::io::Result<()> {
+        TcpPacket::async_write(&mut self.stream, msg).await
+    }
+
+    /// Simulates raw bytes arriving from the client, such as a malformed packet
+    pub async fn inject_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        self.stream.write_all(bytes).await
+    }
+
+    /// Waits for the next message the handler sends. `None` once the handler has dropped the
+    /// connection and every message has been returned
+    pub async fn next_sent(&mut self) -> std::io::Result<Option<Message<Rq, Rs>>> {
+        match TcpPacket::async_read_buffered(&mut self.stream, &mut self.buffer).await? {
+            PacketRead::Message(msg) => Ok(Some(msg)),
+            PacketRead::Disconnected | PacketRead::Empty => Ok(None),
+            PacketRead::Malformed { reason, .. } => {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reason))
+            }
+        }
+    }
+
+    /// Waits for the handler to send `count` more messages and returns them.
+    /// Fails with `UnexpectedEof` if it drops the connection first
+    pub async fn wait_for_sent(&mut self, count: usize) -> std::io::Result<Vec<Message<Rq, Rs>>> {
+        let mut sent = Vec::with_capacity(count);
+        while sent.len() < count {
+            let msg = self.next_sent().await?.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("handler disconnected after sending {} messages", sent.len()),
+                )
+            })?;
+            sent.push(msg);
+        }
+        Ok(sent)
+    }
+
+    /// Every message the handler has sent that has not been taken yet, without waiting for more.
+    /// See `wait_for_sent` to wait for them instead
+    pub fn sent_messages(&mut self) -> std::io::Result<Vec<Message<Rq, Rs>>> {
+        use tokio::io::AsyncRead;
+
+        let mut cx = core::task::Context::from_waker(futures_util::task::noop_waker_ref());
+        let mut chunk = [0u8; 1024];
+        loop {
+            let mut read = tokio::io::ReadBuf::new(&mut chunk);
+            match core::pin::Pin::new(&mut self.stream).poll_read(&mut cx, &mut read) {
+                core::task::Poll::Ready(Ok(())) if read.filled().is_empty() => break,
+                core::task::Poll::Ready(Ok(())) => self.buffer.extend(read.filled()),
+                core::task::Poll::Ready(Err(err)) => return Err(err),
+                core::task::Poll::Pending => break,
+            }
+        }
+        let mut sent = vec![];
+        while let Some(payload) = self.buffer.next_payload() {
+            let msg = serde_json::from_slice(&payload)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            sent.push(msg);
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<Rq, Rs> crate::tokio::ConnectionLike<Rq, Rs> for MockClientConnection<Rq, Rs>
+where
+    Rq: RequestWrapper + Send,
+    Rs: ResponseWrapper + Send,
+{
+    async fn send(&mut self, msg: Message<Rq, Rs>) -> Result<(), crate::SeraphicError> {
+        use tokio::io::AsyncWriteExt;
+        // written from the packet so `msg` isn't held across the write, and needn't be `Sync`
+        let packet = TcpPacket::from(&msg);
+        self.stream.write_all(packet.buffer()).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Option<Message<Rq, Rs>>, crate::SeraphicError> {
+        Ok(self.next_sent().await?)
+    }
+}
@@ -1,11 +1,37 @@
-use crate::packet::{header_size, PacketRead, TcpPacket};
-use std::io::ErrorKind;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+pub mod codec;
+pub mod connection;
+pub mod server;
+#[cfg(feature = "ws")]
+mod ws;
+
+use crate::packet::{header_size, PacketBuffer, PacketRead, TcpPacket};
+pub use codec::{framed, framed_io, PacketCodec};
+pub use connection::{ClientConnection, Connection, ConnectionLike, MessageSink, MessageStream};
+pub use server::{
+    AsyncServerConnection, ConnectionInfo, JoinOutcome, ListenOptions, Server,
+    ServerConnectionHandler, ServerHandlerResult,
+};
+use std::{io::ErrorKind, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+/// Bounds on how long `TcpPacket::async_read_timeout` waits. `None` waits forever
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadTimeouts {
+    /// How long to wait for a packet to start
+    pub idle: Option<Duration>,
+    /// How long a packet has to finish once its first bytes have arrived, so a peer that stalls
+    /// halfway through one can't hold the reader
+    pub frame: Option<Duration>,
+}
 
 impl<T> TcpPacket<T>
 where
     T: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug,
 {
+    /// Like `TcpPacket::read`. Not cancel safe, see `async_read_buffered` for that
     pub async fn async_read<R>(inp: &mut R) -> std::io::Result<PacketRead<T>>
     where
         R: AsyncRead + std::marker::Unpin,
@@ -31,34 +57,117 @@ where
                     return Ok(PacketRead::Empty);
                 }
                 Err(err) => {
-                    return Err(std::io::Error::other(format!(
-                        "unexepect error when reading header: {err:#?}\nbuffer: {}",
-                        String::from_utf8_lossy(&buffer)
-                    )));
+                    return Err(std::io::Error::new(
+                        err.kind(),
+                        format!(
+                            "unexepect error when reading header: {err:#?}\nbuffer: {}",
+                            String::from_utf8_lossy(&buffer)
+                        ),
+                    ));
                 }
             }
         }
-        let size: usize = size.ok_or(std::io::Error::other("no content length"))?;
+        let size: usize = size.ok_or(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "no content length",
+        ))?;
         tracing::debug!("got payload size from header: {size}");
         buffer.resize(size, 0);
         match inp.read_exact(&mut buffer).await {
-            Ok(_) => {
-                let typ = serde_json::from_slice::<T>(&buffer).map_err(|err| {
-                    std::io::Error::other(format!(
-                        "malformed payload: {}\nErr: {err:#?}",
-                        String::from_utf8_lossy(&buffer),
-                    ))
-                })?;
-                Ok(PacketRead::Message(typ))
-            }
-            Err(err) if err.kind() == ErrorKind::WouldBlock => {
-                return Ok(PacketRead::Empty);
-            }
-            Err(err) => {
-                return Err(std::io::Error::other(format!(
+            Ok(_) => Ok(match serde_json::from_slice::<T>(&buffer) {
+                Ok(typ) => PacketRead::Message(typ),
+                Err(err) => PacketRead::Malformed {
+                    payload: buffer,
+                    reason: err.to_string(),
+                },
+            }),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(PacketRead::Empty),
+            Err(err) => Err(std::io::Error::new(
+                err.kind(),
+                format!(
                     "unexepect error when reading payload: {err:#?}\nbuffer: {}",
                     String::from_utf8_lossy(&buffer)
-                )));
+                ),
+            )),
+        }
+    }
+
+    /// Like `async_read`, but partially read packets are kept in `buffer` rather than lost if the
+    /// returned future is dropped, so it is safe to use in `tokio::select!`.
+    /// Payloads that fail to deserialize are returned as `PacketRead::Malformed` instead of an error.
+    /// Never returns `PacketRead::Empty`
+    pub async fn async_read_buffered<R>(
+        inp: &mut R,
+        buffer: &mut PacketBuffer,
+    ) -> std::io::Result<PacketRead<T>>
+    where
+        R: AsyncRead + std::marker::Unpin,
+    {
+        Self::async_read_timeout(inp, buffer, ReadTimeouts::default()).await
+    }
+
+    /// Like `async_read_buffered`, bounded by `timeouts`. Returns `PacketRead::Empty` if nothing
+    /// arrives within `timeouts.idle`, and a `TimedOut` error if a packet that has started
+    /// isn't finished within `timeouts.frame`.
+    /// When the packet started is kept in `buffer`, so the frame timeout holds across calls that
+    /// were cancelled part way through a packet.
+    pub async fn async_read_timeout<R>(
+        inp: &mut R,
+        buffer: &mut PacketBuffer,
+        timeouts: ReadTimeouts,
+    ) -> std::io::Result<PacketRead<T>>
+    where
+        R: AsyncRead + std::marker::Unpin,
+    {
+        let mut chunk = [0u8; 1024];
+        loop {
+            if let Some(payload) = buffer.next_payload() {
+                tracing::debug!("got payload of size: {}", payload.len());
+                return Ok(match serde_json::from_slice::<T>(&payload) {
+                    Ok(typ) => PacketRead::Message(typ),
+                    Err(err) => PacketRead::Malformed {
+                        payload,
+                        reason: err.to_string(),
+                    },
+                });
+            }
+            let deadline = if buffer.is_empty() {
+                timeouts.idle.map(|idle| Instant::now() + idle)
+            } else {
+                // kept in the buffer, so a caller that drops this future and reads again
+                // doesn't get a fresh deadline for the same packet
+                let started = *buffer
+                    .frame_started
+                    .get_or_insert_with(|| Instant::now().into_std());
+                timeouts
+                    .frame
+                    .map(|frame| Instant::from_std(started) + frame)
+            };
+            let read = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, inp.read(&mut chunk)).await,
+                None => Ok(inp.read(&mut chunk).await),
+            };
+            let Ok(read) = read else {
+                if buffer.is_empty() {
+                    return Ok(PacketRead::Empty);
+                }
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "packet was not finished within {:?}",
+                        timeouts.frame.unwrap_or_default()
+                    ),
+                ));
+            };
+            match read? {
+                0 if buffer.is_empty() => return Ok(PacketRead::Disconnected),
+                0 => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "disconnected in the middle of a packet",
+                    ))
+                }
+                n => buffer.extend(&chunk[..n]),
             }
         }
     }
@@ -68,7 +177,21 @@ where
         W: AsyncWrite + std::marker::Unpin,
     {
         let packet = Self::from(typ);
-        let _ = out.write_all(&packet.buffer).await?;
+        out.write_all(&packet.buffer).await?;
+        out.flush().await?;
+        Ok(())
+    }
+
+    /// Writes every message in `messages` as its own packet, but with a single `write_all`
+    pub async fn async_write_batch<W>(out: &mut W, messages: &[T]) -> std::io::Result<()>
+    where
+        W: AsyncWrite + std::marker::Unpin,
+    {
+        let mut buffer = vec![];
+        for typ in messages {
+            buffer.extend_from_slice(&Self::from(typ).buffer);
+        }
+        out.write_all(&buffer).await?;
         out.flush().await?;
         Ok(())
     }
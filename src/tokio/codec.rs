@@ -0,0 +1,119 @@
+//! `tokio_util::codec` support, for driving the packet framing without a `Connection`
+use crate::{
+    packet::{header_size, PacketRead},
+    Message,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{io::ErrorKind, marker::PhantomData};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+use tokio_util::{
+    bytes::{Buf, BufMut, BytesMut},
+    codec::{Decoder, Encoder, FramedRead, FramedWrite},
+};
+
+/// Frames `T` the way `TcpPacket` does, so either end can be a `Connection` or `Server`.
+/// Decoded items are only ever `PacketRead::Message` or `PacketRead::Malformed`
+pub struct PacketCodec<T> {
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> PacketCodec<T> {
+    pub fn new() -> Self {
+        Self {
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for PacketCodec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Decoder for PacketCodec<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = PacketRead<T>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Self::Item>> {
+        let Some(header) = src.get(..header_size()) else {
+            return Ok(None);
+        };
+        let size = u32::from_le_bytes(header.try_into().expect("header is 4 bytes")) as usize;
+        let end = header_size() + size;
+        if src.len() < end {
+            src.reserve(end - src.len());
+            return Ok(None);
+        }
+        src.advance(header_size());
+        let payload = src.split_to(size);
+        Ok(Some(match serde_json::from_slice::<T>(&payload) {
+            Ok(typ) => PacketRead::Message(typ),
+            Err(err) => PacketRead::Malformed {
+                payload: payload.to_vec(),
+                reason: err.to_string(),
+            },
+        }))
+    }
+}
+
+impl<T> Encoder<T> for PacketCodec<T>
+where
+    T: Serialize,
+{
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> std::io::Result<()> {
+        let payload = serde_json::to_vec(&item)?;
+        let size = u32::try_from(payload.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "message is too large"))?;
+        dst.reserve(header_size() + payload.len());
+        dst.put_u32_le(size);
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+pub type MessageWriter<W, Rq, Rs> = FramedWrite<W, PacketCodec<Message<Rq, Rs>>>;
+pub type MessageReader<R, Rq, Rs> = FramedRead<R, PacketCodec<Message<Rq, Rs>>>;
+
+/// Splits `stream` into framed halves, a `Sink` of messages and a `Stream` of what is read
+pub fn framed<Rq, Rs>(
+    stream: TcpStream,
+) -> (
+    MessageWriter<OwnedWriteHalf, Rq, Rs>,
+    MessageReader<OwnedReadHalf, Rq, Rs>,
+) {
+    let (reader, writer) = stream.into_split();
+    (
+        FramedWrite::new(writer, PacketCodec::new()),
+        FramedRead::new(reader, PacketCodec::new()),
+    )
+}
+
+/// Like `framed`, for any other transport
+#[allow(clippy::type_complexity)]
+pub fn framed_io<S, Rq, Rs>(
+    io: S,
+) -> (
+    MessageWriter<WriteHalf<S>, Rq, Rs>,
+    MessageReader<ReadHalf<S>, Rq, Rs>,
+)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, writer) = tokio::io::split(io);
+    (
+        FramedWrite::new(writer, PacketCodec::new()),
+        FramedRead::new(reader, PacketCodec::new()),
+    )
+}
@@ -0,0 +1,817 @@
+use crate::{
+    error::Error,
+    packet::{PacketBuffer, PacketRead, TcpPacket},
+    tokio::ReadTimeouts,
+    Message, RequestId, RequestWrapper, ResponseWrapper, RpcRequest, SeraphicError,
+};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, oneshot, Mutex},
+    task::JoinHandle,
+};
+use tokio_util::sync::{CancellationToken, PollSender};
+
+/// How many messages can wait in `Connection::sender` or `Connection::receiver`.
+/// Past that, messages for `receiver` are kept by the read task so answers to
+/// `Connection::request` still get through
+pub const CHANNEL_CAPACITY: usize = 32;
+
+/// The client side of a connection. A read task feeds `receiver` with messages from the
+/// server, and a write task sends whatever is put in `sender`
+pub struct Connection<Rq, Rs> {
+    pub sender: mpsc::Sender<Message<Rq, Rs>>,
+    pub receiver: mpsc::Receiver<Message<Rq, Rs>>,
+    tasks: Tasks,
+    requests: Arc<Requests<Rq, Rs>>,
+}
+
+/// Where the answer to each request waiting on `Connection::request` goes
+type Pending<Rq, Rs> = HashMap<RequestId, oneshot::Sender<Message<Rq, Rs>>>;
+
+/// Requests sent by `Connection::request` that are waiting for their answer
+struct Requests<Rq, Rs> {
+    next_id: AtomicI64,
+    /// `None` once the read task has stopped, so nothing else can be answered
+    pending: std::sync::Mutex<Option<Pending<Rq, Rs>>>,
+}
+
+impl<Rq, Rs> Requests<Rq, Rs> {
+    fn new() -> Self {
+        Self {
+            // 0 is left for initialization
+            next_id: AtomicI64::new(1),
+            pending: std::sync::Mutex::new(Some(HashMap::new())),
+        }
+    }
+
+    fn register(&self) -> Result<PendingRequest<'_, Rq, Rs>, SeraphicError> {
+        let id = RequestId::from(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, answer) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("not poisoned")
+            .as_mut()
+            .ok_or(SeraphicError::Disconnected)?
+            .insert(id.clone(), sender);
+        Ok(PendingRequest {
+            requests: self,
+            id,
+            answer,
+        })
+    }
+
+    /// Hands `msg` to the request it answers. Anything else is given back
+    fn answer(&self, msg: Message<Rq, Rs>) -> Option<Message<Rq, Rs>> {
+        if let Message::Req { .. } = msg {
+            return Some(msg);
+        }
+        let answer = self
+            .pending
+            .lock()
+            .expect("not poisoned")
+            .as_mut()
+            .and_then(|pending| pending.remove(msg.id()));
+        match answer {
+            Some(answer) => {
+                // the request may have been dropped since
+                let _ = answer.send(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+
+    fn close(&self) {
+        self.pending.lock().expect("not poisoned").take();
+    }
+}
+
+/// Forgets its request when dropped, so a cancelled `Connection::request` leaves nothing behind
+struct PendingRequest<'a, Rq, Rs> {
+    requests: &'a Requests<Rq, Rs>,
+    id: RequestId,
+    answer: oneshot::Receiver<Message<Rq, Rs>>,
+}
+
+impl<Rq, Rs> Drop for PendingRequest<'_, Rq, Rs> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.requests.pending.lock().expect("not poisoned").as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// The read and write tasks behind a `Connection` or `ClientConnection`
+struct Tasks {
+    shutdown: CancellationToken,
+    reader: JoinHandle<std::io::Result<()>>,
+    writer: JoinHandle<std::io::Result<()>>,
+    written: Arc<WriteProgress>,
+}
+
+/// How many messages the write task has written, so a `MessageSink` can tell when it is flushed
+#[derive(Default)]
+struct WriteProgress {
+    count: AtomicU64,
+    waker: std::sync::Mutex<Option<Waker>>,
+}
+
+impl WriteProgress {
+    fn count(&self) -> u64 {
+        self.count.load(Ordering::Acquire)
+    }
+
+    fn advance(&self) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().expect("not poisoned").take() {
+            waker.wake();
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        *self.waker.lock().expect("not poisoned") = Some(waker.clone());
+    }
+}
+
+impl Tasks {
+    #[allow(clippy::type_complexity)]
+    fn spawn<Rq, Rs, R, W>(
+        reader: R,
+        writer: W,
+        requests: Arc<Requests<Rq, Rs>>,
+        timeouts: ReadTimeouts,
+    ) -> (
+        Self,
+        mpsc::Sender<Message<Rq, Rs>>,
+        mpsc::Receiver<Message<Rq, Rs>>,
+    )
+    where
+        Rq: RequestWrapper + Send + 'static,
+        Rs: ResponseWrapper + Send + 'static,
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let shutdown = CancellationToken::new();
+        let (sender, outgoing) = mpsc::channel(CHANNEL_CAPACITY);
+        let (incoming, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let written = Arc::new(WriteProgress::default());
+        let tasks = Self {
+            reader: tokio::spawn(read_task(
+                reader,
+                incoming,
+                shutdown.clone(),
+                requests,
+                timeouts,
+            )),
+            writer: tokio::spawn(write_task(
+                writer,
+                outgoing,
+                shutdown.clone(),
+                Arc::clone(&written),
+            )),
+            shutdown,
+            written,
+        };
+        (tasks, sender, receiver)
+    }
+
+    async fn join(self) -> std::io::Result<()> {
+        let (read, write) = tokio::join!(self.reader, self.writer);
+        read.map_err(std::io::Error::other)??;
+        write.map_err(std::io::Error::other)?
+    }
+
+    async fn shutdown(self, timeout: Duration) -> std::io::Result<()> {
+        self.shutdown.cancel();
+        let tasks = [self.reader.abort_handle(), self.writer.abort_handle()];
+        match tokio::time::timeout(timeout, self.join()).await {
+            Ok(joined) => joined,
+            Err(_) => {
+                tasks.iter().for_each(|task| task.abort());
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connection did not shut down within {timeout:?}"),
+                ))
+            }
+        }
+    }
+}
+
+impl<Rq, Rs> Connection<Rq, Rs>
+where
+    Rq: RequestWrapper + Send + 'static,
+    Rs: ResponseWrapper + Send + 'static,
+{
+    pub async fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Self::connect_with(addr, ReadTimeouts::default()).await
+    }
+
+    /// Connects with the read task bounded by `timeouts`. Going idle for longer than
+    /// `timeouts.idle`, or stalling within a packet, ends it with a `TimedOut` error
+    pub async fn connect_with(
+        addr: impl ToSocketAddrs,
+        timeouts: ReadTimeouts,
+    ) -> std::io::Result<Self> {
+        Ok(Self::new_with(TcpStream::connect(addr).await?, timeouts))
+    }
+
+    pub fn new(stream: TcpStream) -> Self {
+        Self::new_with(stream, ReadTimeouts::default())
+    }
+
+    /// See `Connection::connect_with`
+    pub fn new_with(stream: TcpStream, timeouts: ReadTimeouts) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self::from_halves(reader, writer, timeouts)
+    }
+
+    /// Connects to a `Server::listen_ws` server, `url` being like `ws://host:port/path`
+    #[cfg(feature = "ws")]
+    pub async fn connect_ws(url: &str) -> Result<Self, SeraphicError> {
+        let (reader, writer) = tokio::io::split(super::ws::connect(url).await?);
+        Ok(Self::from_halves(reader, writer, ReadTimeouts::default()))
+    }
+
+    fn from_halves<R, W>(reader: R, writer: W, timeouts: ReadTimeouts) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let requests = Arc::new(Requests::new());
+        let (tasks, sender, receiver) =
+            Tasks::spawn(reader, writer, Arc::clone(&requests), timeouts);
+        Self {
+            sender,
+            receiver,
+            tasks,
+            requests,
+        }
+    }
+
+    /// Sends `req` and waits for its answer, which is taken out of the messages from the server
+    /// before they reach `receiver`. Ids count up from 1, so anything sent through `sender`
+    /// alongside should use different ones.
+    /// Dropping the future before it is answered forgets the request
+    pub async fn request<R>(&self, req: R) -> Result<Result<R::Response, Error>, SeraphicError>
+    where
+        R: RpcRequest,
+        Rq: From<R>,
+    {
+        let mut pending = self.requests.register()?;
+        self.sender
+            .send(Message::Req {
+                id: pending.id.clone(),
+                req: Rq::from(req),
+            })
+            .await
+            .map_err(|_| SeraphicError::Disconnected)?;
+        let answer = (&mut pending.answer).await;
+        match answer.map_err(|_| SeraphicError::Disconnected)? {
+            Message::Res { id, res } => res.into_res(id).try_into_res::<R::Response>(),
+            Message::Err { err, .. } => Ok(Err(err)),
+            Message::Req { .. } => unreachable!("requests are never answers"),
+        }
+    }
+
+    /// Like `request`, but fails with a `TimedOut` io error if there is no answer within `timeout`
+    pub async fn request_timeout<R>(
+        &self,
+        req: R,
+        timeout: Duration,
+    ) -> Result<Result<R::Response, Error>, SeraphicError>
+    where
+        R: RpcRequest,
+        Rq: From<R>,
+    {
+        match tokio::time::timeout(timeout, self.request(req)).await {
+            Ok(answer) => answer,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("{} was not answered in {timeout:?}", R::method()),
+            )
+            .into()),
+        }
+    }
+
+    /// Cancelling it stops both tasks. Anything already in `sender` is still written
+    pub fn shutdown_token(&self) -> &CancellationToken {
+        &self.tasks.shutdown
+    }
+
+    /// Waits for both tasks to finish, which happens once the server disconnects and every
+    /// clone of `sender` is dropped, or the connection is shut down
+    pub async fn join(self) -> std::io::Result<()> {
+        let Self {
+            sender,
+            receiver,
+            tasks,
+            ..
+        } = self;
+        // nothing can be sent or received once the connection has been given up
+        drop((sender, receiver));
+        tasks.join().await
+    }
+
+    /// Shuts the connection down and joins it, aborting the tasks if that takes longer
+    /// than `timeout`
+    pub async fn shutdown(self, timeout: Duration) -> std::io::Result<()> {
+        self.tasks.shutdown(timeout).await
+    }
+
+    /// The messages from the server as a `Stream`. Clone `sender` first to keep sending
+    pub fn into_stream(self) -> MessageStream<Rq, Rs> {
+        MessageStream {
+            receiver: self.receiver,
+            tasks: self.tasks,
+            done: false,
+        }
+    }
+
+    /// Splits the connection into a `Sink` for messages to the server and a `Stream` of its
+    /// messages. Flushing the sink waits for what it was given to be written, so clones of
+    /// `sender` taken before splitting should not be used alongside it
+    pub fn into_split(self) -> (MessageSink<Rq, Rs>, MessageStream<Rq, Rs>) {
+        let sink = MessageSink {
+            sender: PollSender::new(self.sender),
+            written: Arc::clone(&self.tasks.written),
+            sent: self.tasks.written.count(),
+        };
+        let stream = MessageStream {
+            receiver: self.receiver,
+            tasks: self.tasks,
+            done: false,
+        };
+        (sink, stream)
+    }
+}
+
+/// See `Connection::into_split`. Closing it lets the write task finish, which shuts down the
+/// writing half of the socket once everything queued has been written
+pub struct MessageSink<Rq, Rs> {
+    sender: PollSender<Message<Rq, Rs>>,
+    written: Arc<WriteProgress>,
+    /// What `written` has to reach for the sink to be flushed
+    sent: u64,
+}
+
+impl<Rq, Rs> Sink<Message<Rq, Rs>> for MessageSink<Rq, Rs>
+where
+    Rq: Send + 'static,
+    Rs: Send + 'static,
+{
+    type Error = SeraphicError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut()
+            .sender
+            .poll_reserve(cx)
+            .map_err(|_| SeraphicError::Disconnected)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: Message<Rq, Rs>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.sender
+            .send_item(msg)
+            .map_err(|_| SeraphicError::Disconnected)?;
+        this.sent += 1;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.written.count() >= this.sent {
+            return Poll::Ready(Ok(()));
+        }
+        // registering before checking again means an advance in between can't be missed
+        this.written.register(cx.waker());
+        if this.written.count() >= this.sent {
+            Poll::Ready(Ok(()))
+        } else if this.sender.is_closed() {
+            // the write task is gone, what is left will never be written
+            Poll::Ready(Err(SeraphicError::Disconnected))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.get_mut().sender.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// See `Connection::into_stream`. If the read task stopped because of a transport error,
+/// that error is the last item before the stream ends
+pub struct MessageStream<Rq, Rs> {
+    receiver: mpsc::Receiver<Message<Rq, Rs>>,
+    tasks: Tasks,
+    done: bool,
+}
+
+impl<Rq, Rs> MessageStream<Rq, Rs> {
+    /// Cancelling it ends the stream
+    pub fn shutdown_token(&self) -> &CancellationToken {
+        &self.tasks.shutdown
+    }
+}
+
+impl<Rq, Rs> Stream for MessageStream<Rq, Rs> {
+    type Item = Result<Message<Rq, Rs>, SeraphicError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if let Some(msg) = ready!(this.receiver.poll_recv(cx)) {
+            return Poll::Ready(Some(Ok(msg)));
+        }
+        // the read task has stopped, find out why
+        let read = ready!(Pin::new(&mut this.tasks.reader).poll(cx));
+        this.done = true;
+        Poll::Ready(match read {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(Err(err.into())),
+            Err(err) => Some(Err(std::io::Error::other(err).into())),
+        })
+    }
+}
+
+/// How long `ClientConnection::initialize` waits for the server by default
+pub const DEFAULT_INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `Connection` whose channels can be shared between tasks, and that knows how to
+/// initialize itself with the server
+pub struct ClientConnection<Rq, Rs> {
+    sender: mpsc::Sender<Message<Rq, Rs>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Message<Rq, Rs>>>>,
+    tasks: Tasks,
+    init_timeout: Duration,
+}
+
+impl<Rq, Rs> From<Connection<Rq, Rs>> for ClientConnection<Rq, Rs> {
+    fn from(conn: Connection<Rq, Rs>) -> Self {
+        Self {
+            sender: conn.sender,
+            receiver: Arc::new(Mutex::new(conn.receiver)),
+            tasks: conn.tasks,
+            init_timeout: DEFAULT_INIT_TIMEOUT,
+        }
+    }
+}
+
+impl<Rq, Rs> ClientConnection<Rq, Rs>
+where
+    Rq: RequestWrapper + Send + 'static,
+    Rs: ResponseWrapper + Send + 'static,
+{
+    pub async fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Connection::connect(addr).await?.into())
+    }
+
+    /// See `Connection::connect_with`
+    pub async fn connect_with(
+        addr: impl ToSocketAddrs,
+        timeouts: ReadTimeouts,
+    ) -> std::io::Result<Self> {
+        Ok(Connection::connect_with(addr, timeouts).await?.into())
+    }
+
+    /// See `Connection::connect_ws`
+    #[cfg(feature = "ws")]
+    pub async fn connect_ws(url: &str) -> Result<Self, SeraphicError> {
+        Ok(Connection::connect_ws(url).await?.into())
+    }
+
+    pub fn with_init_timeout(mut self, timeout: Duration) -> Self {
+        self.init_timeout = timeout;
+        self
+    }
+
+    pub fn sender(&self) -> mpsc::Sender<Message<Rq, Rs>> {
+        self.sender.clone()
+    }
+
+    /// Tasks sharing the receiver take turns by locking it
+    pub fn receiver(&self) -> Arc<Mutex<mpsc::Receiver<Message<Rq, Rs>>>> {
+        Arc::clone(&self.receiver)
+    }
+
+    /// Sends `req` with id 0 and waits for the server to answer it, as
+    /// `AsyncServerConnection::initialize` does. The server must not send anything else first.
+    /// Fails with a `TimedOut` io error if there is no answer within the init timeout
+    pub async fn initialize<I>(&self, req: I) -> Result<Result<I::Response, Error>, SeraphicError>
+    where
+        I: RpcRequest,
+        Rq: From<I>,
+    {
+        self.initialize_until(req, std::future::pending::<()>())
+            .await
+    }
+
+    /// Like `initialize`, but fails with `SeraphicError::Aborted` if `cancel` completes first,
+    /// such as `tokio::signal::ctrl_c()`. The connection can still be shut down afterwards
+    pub async fn initialize_until<I, F>(
+        &self,
+        req: I,
+        cancel: F,
+    ) -> Result<Result<I::Response, Error>, SeraphicError>
+    where
+        I: RpcRequest,
+        Rq: From<I>,
+        F: Future,
+    {
+        // each step only takes a message once it is ready, so dropping it midway loses nothing
+        tokio::select! {
+            _ = cancel => Err(SeraphicError::Aborted),
+            init = self.send_initialize(req) => init,
+        }
+    }
+
+    async fn send_initialize<I>(&self, req: I) -> Result<Result<I::Response, Error>, SeraphicError>
+    where
+        I: RpcRequest,
+        Rq: From<I>,
+    {
+        let id = RequestId::from(0);
+        self.sender
+            .send(Message::Req {
+                id: id.clone(),
+                req: Rq::from(req),
+            })
+            .await
+            .map_err(|_| SeraphicError::Disconnected)?;
+
+        let mut receiver = self.receiver.lock().await;
+        let answer = match tokio::time::timeout(self.init_timeout, receiver.recv()).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Err(SeraphicError::Disconnected),
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!(
+                        "server did not answer initialization in {:?}",
+                        self.init_timeout
+                    ),
+                )
+                .into())
+            }
+        };
+        initialize_answer::<I, _, _>(&id, answer)
+    }
+
+    /// See `Connection::join`
+    pub async fn join(self) -> std::io::Result<()> {
+        let Self {
+            sender,
+            receiver,
+            tasks,
+            ..
+        } = self;
+        drop((sender, receiver));
+        tasks.join().await
+    }
+
+    /// See `Connection::shutdown`
+    pub async fn shutdown(self, timeout: Duration) -> std::io::Result<()> {
+        self.tasks.shutdown(timeout).await
+    }
+}
+
+/// The client's side of a conversation with a server, so code that drives one can run against
+/// a `ClientConnection` or a `testing::MockClientConnection` alike
+pub trait ConnectionLike<Rq, Rs> {
+    fn send(
+        &mut self,
+        msg: Message<Rq, Rs>,
+    ) -> impl Future<Output = Result<(), SeraphicError>> + Send;
+
+    /// The next message from the server. `None` once it has disconnected
+    fn recv(
+        &mut self,
+    ) -> impl Future<Output = Result<Option<Message<Rq, Rs>>, SeraphicError>> + Send;
+}
+
+impl<Rq, Rs> ConnectionLike<Rq, Rs> for ClientConnection<Rq, Rs>
+where
+    Rq: Send,
+    Rs: Send,
+{
+    async fn send(&mut self, msg: Message<Rq, Rs>) -> Result<(), SeraphicError> {
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| SeraphicError::Disconnected)
+    }
+
+    async fn recv(&mut self) -> Result<Option<Message<Rq, Rs>>, SeraphicError> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+}
+
+async fn read_task<Rq, Rs, R>(
+    mut reader: R,
+    incoming: mpsc::Sender<Message<Rq, Rs>>,
+    shutdown: CancellationToken,
+    requests: Arc<Requests<Rq, Rs>>,
+    timeouts: ReadTimeouts,
+) -> std::io::Result<()>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    R: AsyncRead + Unpin,
+{
+    let mut parked = VecDeque::new();
+    let result = read_loop(
+        &mut reader,
+        &incoming,
+        shutdown.clone(),
+        &requests,
+        timeouts,
+        &mut parked,
+    )
+    .await;
+    // whatever is still waiting will never be answered
+    requests.close();
+    // what was read before the end still reaches `receiver`, unless we are shutting down
+    tokio::select! {
+        _ = shutdown.cancelled() => {}
+        _ = async {
+            for msg in parked {
+                if incoming.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        } => {}
+    }
+    result
+}
+
+async fn read_loop<Rq, Rs, R>(
+    reader: &mut R,
+    incoming: &mpsc::Sender<Message<Rq, Rs>>,
+    shutdown: CancellationToken,
+    requests: &Requests<Rq, Rs>,
+    timeouts: ReadTimeouts,
+    parked: &mut VecDeque<Message<Rq, Rs>>,
+) -> std::io::Result<()>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = PacketBuffer::new();
+    loop {
+        let read = tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            permit = incoming.reserve(), if !parked.is_empty() => {
+                let Ok(permit) = permit else {
+                    return Ok(());
+                };
+                permit.send(parked.pop_front().expect("checked by the branch condition"));
+                continue;
+            }
+            // cancel safe, a packet read part way is kept in `buffer`
+            read = TcpPacket::async_read_timeout(reader, &mut buffer, timeouts) => read?,
+        };
+        match read {
+            PacketRead::Message(msg) => {
+                let Some(msg) = requests.answer(msg) else {
+                    continue;
+                };
+                // a full `receiver` must not hold up the answers to pending requests behind
+                // this message, so it waits in `parked` instead, keeping its order
+                if !parked.is_empty() {
+                    parked.push_back(msg);
+                    continue;
+                }
+                match incoming.try_send(msg) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(msg)) => parked.push_back(msg),
+                    // nobody is listening anymore
+                    Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+                }
+            }
+            PacketRead::Malformed { reason, .. } => {
+                tracing::warn!("server sent malformed packet: {reason}");
+            }
+            PacketRead::Disconnected => return Ok(()),
+            PacketRead::Empty => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!(
+                        "nothing was read from the server within {:?}",
+                        timeouts.idle.unwrap_or_default()
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+/// Takes `msg` by value so it doesn't have to be `Sync` to be written from a task
+async fn write<Rq, Rs, W>(writer: &mut W, msg: Message<Rq, Rs>) -> std::io::Result<()>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    W: AsyncWrite + Unpin,
+{
+    let packet = TcpPacket::from(&msg);
+    writer.write_all(packet.buffer()).await?;
+    writer.flush().await
+}
+
+async fn write_task<Rq, Rs, W>(
+    mut writer: W,
+    mut outgoing: mpsc::Receiver<Message<Rq, Rs>>,
+    shutdown: CancellationToken,
+    written: Arc<WriteProgress>,
+) -> std::io::Result<()>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    W: AsyncWrite + Unpin,
+{
+    let result = write_loop(&mut writer, &mut outgoing, shutdown, &written).await;
+    // a sink waiting on a flush finds out the task is gone
+    drop(outgoing);
+    written.wake();
+    result?;
+    writer.shutdown().await
+}
+
+async fn write_loop<Rq, Rs, W>(
+    writer: &mut W,
+    outgoing: &mut mpsc::Receiver<Message<Rq, Rs>>,
+    shutdown: CancellationToken,
+    written: &WriteProgress,
+) -> std::io::Result<()>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let msg = tokio::select! {
+            msg = outgoing.recv() => msg,
+            _ = shutdown.cancelled() => {
+                // what was queued before the shutdown still goes out
+                while let Ok(msg) = outgoing.try_recv() {
+                    write(writer, msg).await?;
+                    written.advance();
+                }
+                None
+            }
+        };
+        match msg {
+            Some(msg) => {
+                write(writer, msg).await?;
+                written.advance();
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+/// The server's answer to the initialize request sent with `id`, shared by every transport
+pub(crate) fn initialize_answer<I, Rq, Rs>(
+    id: &RequestId,
+    answer: Message<Rq, Rs>,
+) -> Result<Result<I::Response, Error>, SeraphicError>
+where
+    I: RpcRequest,
+    Rs: ResponseWrapper,
+{
+    if answer.id() != id {
+        return Err(SeraphicError::Protocol(format!(
+            "expected the answer to initialization request {id}, got a message with id {}",
+            answer.id()
+        )));
+    }
+    match answer {
+        Message::Res { id, res } => res.into_res(id).try_into_res::<I::Response>(),
+        Message::Err { err, .. } => Ok(Err(err)),
+        Message::Req { .. } => Err(SeraphicError::Protocol(
+            "expected the answer to initialization, got a request".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,1039 @@
+use crate::{
+    error::{Error, ErrorCode, ErrorContext, ErrorKind, DEFAULT_MAX_ERROR_PAYLOAD},
+    msg::IdentifiedResponse,
+    packet::{PacketBuffer, PacketRead, TcpPacket},
+    MainResult, Message, Request, RequestId, RequestWrapper, Response, ResponseWrapper, RpcRequest,
+    RpcResponse, SeraphicError, JSONRPC_FIELD,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream, ToSocketAddrs},
+    task::{JoinError, JoinHandle},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+pub type ServerHandlerResult = MainResult<()>;
+
+/// How many malformed packets a client may send before its connection is dropped
+pub const DEFAULT_MAX_MALFORMED_PACKETS: usize = 5;
+
+/// How long accept loops pause after a failed accept, so running out of file descriptors
+/// doesn't turn into a busy loop
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Defines how the server talks to each client.
+/// `handler` is run in its own task for every accepted connection
+pub trait ServerConnectionHandler<Rq, Rs>: 'static {
+    fn handler(
+        conn: &mut AsyncServerConnection<Rq, Rs>,
+    ) -> impl Future<Output = ServerHandlerResult> + Send;
+}
+
+/// Socket options applied to the listener before it binds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenOptions {
+    /// Lets a restarted server bind while the old socket is still in `TIME_WAIT`
+    pub reuse_addr: bool,
+    /// Lets several listeners share the same port. Ignored on platforms without `SO_REUSEPORT`
+    pub reuse_port: bool,
+    /// Maximum number of pending connections
+    pub backlog: u32,
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        Self {
+            reuse_addr: true,
+            reuse_port: false,
+            backlog: 1024,
+        }
+    }
+}
+
+impl ListenOptions {
+    fn bind(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.set_reuseaddr(self.reuse_addr)?;
+        #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+        socket.set_reuseport(self.reuse_port)?;
+        socket.bind(addr)?;
+        socket.listen(self.backlog)
+    }
+}
+
+/// A snapshot of a connection's statistics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Set by the handler with `AsyncServerConnection::set_tag`
+    pub tags: HashMap<String, String>,
+    pub connected_at: Instant,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    /// True while the handler is waiting in `AsyncServerConnection::initialize`
+    pub is_initializing: bool,
+    /// The connection's shutdown token was cancelled, but the handler may still be running
+    pub shutdown_requested: bool,
+    /// Messages that arrived while the handler was waiting on a response, not yet `recv`'d
+    pub inbound_pending: u64,
+    /// The most messages that have been waiting at once
+    pub inbound_high_water: u64,
+}
+
+/// Updated by the connection's task and read by the server
+#[derive(Debug)]
+struct ConnectionStats {
+    connected_at: Instant,
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    is_initializing: AtomicBool,
+    inbound_pending: AtomicU64,
+    inbound_high_water: AtomicU64,
+    tags: Mutex<HashMap<String, String>>,
+}
+
+impl ConnectionStats {
+    fn new() -> Self {
+        Self {
+            connected_at: Instant::now(),
+            messages_received: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            is_initializing: AtomicBool::new(false),
+            inbound_pending: AtomicU64::new(0),
+            inbound_high_water: AtomicU64::new(0),
+            tags: Mutex::default(),
+        }
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            tags: self.tags.lock().unwrap().clone(),
+            connected_at: self.connected_at,
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            is_initializing: self.is_initializing.load(Ordering::Relaxed),
+            shutdown_requested: false,
+            inbound_pending: self.inbound_pending.load(Ordering::Relaxed),
+            inbound_high_water: self.inbound_high_water.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_inbound_pending(&self, pending: usize) {
+        let pending = pending as u64;
+        self.inbound_pending.store(pending, Ordering::Relaxed);
+        self.inbound_high_water
+            .fetch_max(pending, Ordering::Relaxed);
+    }
+}
+
+struct ConnectionHandle {
+    task: JoinHandle<ServerHandlerResult>,
+    shutdown: CancellationToken,
+    stats: Arc<ConnectionStats>,
+}
+
+impl ConnectionHandle {
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            shutdown_requested: self.shutdown.is_cancelled(),
+            ..self.stats.info()
+        }
+    }
+}
+
+/// Limits each connection is created with
+#[derive(Debug, Clone, Copy)]
+struct ConnectionLimits {
+    max_malformed_packets: usize,
+    max_error_payload: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_malformed_packets: DEFAULT_MAX_MALFORMED_PACKETS,
+            max_error_payload: DEFAULT_MAX_ERROR_PAYLOAD,
+        }
+    }
+}
+
+/// Applied to each message a connection receives, see `Server::map_incoming`
+type IncomingMap<Rq, Rs> = Arc<dyn Fn(Message<Rq, Rs>) -> Option<Message<Rq, Rs>> + Send + Sync>;
+
+/// Either half of the client's socket, or of the bridge to its WebSocket
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+fn split_tcp(stream: TcpStream) -> (Reader, Writer) {
+    let (reader, writer) = stream.into_split();
+    (Box::new(reader), Box::new(writer))
+}
+
+pub struct Server<Rq, Rs, H> {
+    listener: TcpListener,
+    shutdown: CancellationToken,
+    connections: HashMap<SocketAddr, ConnectionHandle>,
+    limits: ConnectionLimits,
+    map_incoming: Option<IncomingMap<Rq, Rs>>,
+    /// Set by `listen_ws`, clients are upgraded to WebSocket on this path
+    #[cfg(feature = "ws")]
+    ws_path: Option<String>,
+    marker: PhantomData<(Rq, Rs, H)>,
+}
+
+impl<Rq, Rs, H> Server<Rq, Rs, H>
+where
+    Rq: RequestWrapper + Send + 'static,
+    Rs: ResponseWrapper + Send + 'static,
+    H: ServerConnectionHandler<Rq, Rs>,
+{
+    /// Listens with the default [`ListenOptions`], so `SO_REUSEADDR` is set
+    pub async fn listen(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Self::listen_with(addr, ListenOptions::default()).await
+    }
+
+    /// Listens on the first address `addr` resolves to that can be bound with `opts`
+    pub async fn listen_with(
+        addr: impl ToSocketAddrs,
+        opts: ListenOptions,
+    ) -> std::io::Result<Self> {
+        let mut last_err = None;
+        let mut listener = None;
+        for addr in lookup_host(addr).await? {
+            match opts.bind(addr) {
+                Ok(l) => {
+                    listener = Some(l);
+                    break;
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        let listener = listener.ok_or_else(|| {
+            last_err.unwrap_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "could not resolve to any address",
+                )
+            })
+        })?;
+        Ok(Self {
+            listener,
+            shutdown: CancellationToken::new(),
+            connections: HashMap::new(),
+            limits: ConnectionLimits::default(),
+            map_incoming: None,
+            #[cfg(feature = "ws")]
+            ws_path: None,
+            marker: PhantomData,
+        })
+    }
+
+    /// Like `listen`, but clients connect with a WebSocket handshake on `path`, and then send
+    /// each message as a frame of its own. Handlers don't see the difference
+    #[cfg(feature = "ws")]
+    pub async fn listen_ws(addr: impl ToSocketAddrs, path: &str) -> std::io::Result<Self> {
+        let mut server = Self::listen(addr).await?;
+        server.ws_path = Some(path.to_string());
+        Ok(server)
+    }
+
+    /// The address the listener is bound to, useful after listening on port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Malformed packets are answered with a parse or invalid request error.
+    /// Once a client has sent more than `max` of them, its connection is dropped
+    pub fn with_max_malformed_packets(mut self, max: usize) -> Self {
+        self.limits.max_malformed_packets = max;
+        self
+    }
+
+    /// Caps how many bytes of an offending message are echoed back in an error's data, such as
+    /// a request sent before initializing. Longer payloads are truncated
+    pub fn with_max_error_payload(mut self, bytes: usize) -> Self {
+        self.limits.max_error_payload = bytes;
+        self
+    }
+
+    /// Passes every message a client sends through `f` before the handler sees it.
+    /// `None` drops the message, `Some` replaces it
+    pub fn map_incoming(
+        mut self,
+        f: impl Fn(Message<Rq, Rs>) -> Option<Message<Rq, Rs>> + Send + Sync + 'static,
+    ) -> Self {
+        self.map_incoming = Some(Arc::new(f));
+        self
+    }
+
+    /// Cancelled when the server shuts down. Cancelling a clone of it shuts the server down,
+    /// which stops accepting and signals every connection
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Accepts the next client and spawns a task running `H::handler` for it.
+    /// Handlers that have already returned are forgotten first, with their results logged.
+    /// A client connecting from the address of a handler that is still running is turned away.
+    /// Returns `None` once the server has been shut down
+    pub async fn next(&mut self) -> std::io::Result<Option<SocketAddr>> {
+        let (stream, addr) = loop {
+            let (stream, addr) = tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(None),
+                accepted = self.listener.accept() => accepted?,
+            };
+            self.reap_finished().await;
+            if !self.connections.contains_key(&addr) {
+                break (stream, addr);
+            }
+            tracing::warn!("handler for {addr} is still running, refusing new connection");
+        };
+        tracing::debug!("accepted connection from {addr}");
+        let shutdown = self.shutdown.child_token();
+        let stats = Arc::new(ConnectionStats::new());
+        // tokio tasks can't be named on stable, a span lets logs and panics be tied to the peer
+        let span =
+            tracing::info_span!("seraphic-handler", peer = %addr, tags = tracing::field::Empty);
+        let (conn_shutdown, conn_stats, conn_span) =
+            (shutdown.clone(), Arc::clone(&stats), span.clone());
+        let (limits, map_incoming) = (self.limits, self.map_incoming.clone());
+        #[cfg(feature = "ws")]
+        let ws_path = self.ws_path.clone();
+        let task = tokio::spawn(
+            async move {
+                // the handshake happens here so a slow client doesn't hold up accepting
+                #[cfg(feature = "ws")]
+                let (reader, writer) = match ws_path {
+                    Some(path) => {
+                        let ws = super::ws::accept(stream, &path).await?;
+                        let (reader, writer) = tokio::io::split(ws);
+                        (Box::new(reader) as Reader, Box::new(writer) as Writer)
+                    }
+                    None => split_tcp(stream),
+                };
+                #[cfg(not(feature = "ws"))]
+                let (reader, writer) = split_tcp(stream);
+                let mut conn = AsyncServerConnection::new(
+                    reader,
+                    writer,
+                    addr,
+                    conn_shutdown,
+                    conn_stats,
+                    limits,
+                    map_incoming,
+                );
+                conn.span = conn_span;
+                H::handler(&mut conn).await
+            }
+            .instrument(span),
+        );
+        self.connections.insert(
+            addr,
+            ConnectionHandle {
+                task,
+                shutdown,
+                stats,
+            },
+        );
+        Ok(Some(addr))
+    }
+
+    /// Every client whose handler is still running. A handler that has returned drops out
+    /// right away, whether or not it has been joined or reaped yet
+    pub fn connected_clients(&self) -> HashMap<SocketAddr, ConnectionInfo> {
+        self.connections
+            .iter()
+            .filter(|(_, conn)| !conn.task.is_finished())
+            .map(|(addr, conn)| (*addr, conn.info()))
+            .collect()
+    }
+
+    /// True if a handler for `addr` was spawned and has not returned yet.
+    /// Like `connected_clients`, this doesn't wait for the handler to be joined
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.connections
+            .get(addr)
+            .is_some_and(|conn| !conn.task.is_finished())
+    }
+
+    /// Waits for the handler for `addr` to return and forgets the connection.
+    /// Returns `None` if there is no such connection, which includes a handler that returned
+    /// before a later `next` reaped it. A handler that panicked or was aborted is reported as
+    /// an error
+    pub async fn join_connection(&mut self, addr: &SocketAddr) -> Option<ServerHandlerResult> {
+        let conn = self.connections.remove(addr)?;
+        Some(
+            conn.task
+                .await
+                .unwrap_or_else(|err| Err(handler_join_error(addr, err).into())),
+        )
+    }
+
+    /// `join_connection`, but only if the handler has already returned, so it never waits.
+    /// Returns `None` if there is no such connection or its handler is still running
+    pub async fn try_join_connection(&mut self, addr: &SocketAddr) -> Option<ServerHandlerResult> {
+        if !self.connections.get(addr)?.task.is_finished() {
+            return None;
+        }
+        self.join_connection(addr).await
+    }
+
+    /// Stops the connection to `addr` without touching any other.
+    /// When `graceful`, the connection's shutdown token is cancelled so `recv` returns `None`
+    /// and the handler can wrap up. Otherwise its task is aborted.
+    /// Returns `false` if there is no such connection
+    pub fn disconnect(&mut self, addr: &SocketAddr, graceful: bool) -> bool {
+        let Some(conn) = self.connections.get(addr) else {
+            return false;
+        };
+        if graceful {
+            conn.shutdown.cancel();
+        } else {
+            conn.task.abort();
+        }
+        true
+    }
+
+    /// Accepts clients until the server is shut down.
+    /// A failed accept is logged, and accepting carries on after a short pause
+    pub async fn run(&mut self) -> std::io::Result<()> {
+        loop {
+            match self.next().await {
+                Ok(Some(_)) => {}
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    tracing::warn!("failed to accept a connection: {err}");
+                    tokio::select! {
+                        _ = self.shutdown.cancelled() => return Ok(()),
+                        _ = tokio::time::sleep(ACCEPT_ERROR_BACKOFF) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets every connection whose handler has returned, logging how it went
+    async fn reap_finished(&mut self) {
+        let finished: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.task.is_finished())
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in finished {
+            if let Some(conn) = self.connections.remove(&addr) {
+                log_handler_result(&addr, &conn.task.await);
+            }
+        }
+    }
+
+    /// Signals every connection to shut down and waits for their handlers to return.
+    /// Every connection is joined even if some handlers failed or panicked, and each outcome is
+    /// logged and returned
+    pub async fn shutdown_and_join_all_connections(
+        &mut self,
+    ) -> Vec<(SocketAddr, Result<ServerHandlerResult, JoinError>)> {
+        self.shutdown.cancel();
+        let mut results = Vec::with_capacity(self.connections.len());
+        for (addr, conn) in self.connections.drain() {
+            let result = conn.task.await;
+            log_handler_result(&addr, &result);
+            results.push((addr, result));
+        }
+        results
+    }
+
+    /// Like `shutdown_and_join_all_connections`, but gives up on handlers still running after
+    /// `timeout`. Those are aborted and reported as abandoned rather than awaited
+    pub async fn shutdown_and_join_all_connections_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> JoinOutcome {
+        self.shutdown.cancel();
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut outcome = JoinOutcome::default();
+        for (addr, mut conn) in self.connections.drain() {
+            match tokio::time::timeout_at(deadline, &mut conn.task).await {
+                Ok(result) => outcome.finished.push((addr, result)),
+                Err(_) => {
+                    tracing::warn!("handler for {addr} did not finish in {timeout:?}, aborting");
+                    conn.task.abort();
+                    outcome.abandoned.push(addr);
+                }
+            }
+        }
+        outcome
+    }
+}
+
+fn log_handler_result(addr: &SocketAddr, result: &Result<ServerHandlerResult, JoinError>) {
+    match result {
+        Ok(Ok(())) => tracing::debug!("handler for {addr} finished"),
+        Ok(Err(err)) => tracing::warn!("handler for {addr} returned an error: {err:#?}"),
+        Err(err) => tracing::warn!("handler for {addr} did not finish: {err:#?}"),
+    }
+}
+
+/// Keeps the panic message of a handler that panicked, rather than re-panicking
+fn handler_join_error(addr: &SocketAddr, err: JoinError) -> std::io::Error {
+    if !err.is_panic() {
+        return std::io::Error::other(format!("handler for {addr} was aborted"));
+    }
+    let payload = err.into_panic();
+    let msg = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>");
+    std::io::Error::other(format!("handler for {addr} panicked: {msg}"))
+}
+
+/// Which handlers `Server::shutdown_and_join_all_connections_timeout` waited for
+#[derive(Debug, Default)]
+pub struct JoinOutcome {
+    pub finished: Vec<(SocketAddr, Result<ServerHandlerResult, JoinError>)>,
+    /// Handlers that were still running at the deadline and have been aborted
+    pub abandoned: Vec<SocketAddr>,
+}
+
+/// What `AsyncServerConnection::recv_until` stopped on
+enum Recv<Rq, Rs, T> {
+    Message(Message<Rq, Rs>),
+    /// The client disconnected or the server is shutting down
+    Closed,
+    /// The cancellation future completed first, with this output
+    Cancelled(T),
+}
+
+/// Why a request made by the server to a client did not get an answer
+#[derive(Debug)]
+pub enum RequestError {
+    Io(std::io::Error),
+    Timeout,
+    /// The client hung up or the server shut down before answering
+    Disconnected,
+    /// The request could not be serialized
+    Serialize(SeraphicError),
+    /// The client answered with a response that is not `R::Response`
+    InvalidResponse(SeraphicError),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "io error: {err}"),
+            Self::Timeout => write!(f, "timed out waiting for a response"),
+            Self::Disconnected => write!(f, "disconnected before a response arrived"),
+            Self::Serialize(err) => write!(f, "failed to serialize request: {err}"),
+            Self::InvalidResponse(err) => write!(f, "invalid response: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<std::io::Error> for RequestError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+pub struct AsyncServerConnection<Rq, Rs> {
+    pub addr: SocketAddr,
+    reader: Reader,
+    buffer: PacketBuffer,
+    writer: Writer,
+    shutdown: CancellationToken,
+    /// Messages from the client that arrived while waiting on a response to `request`
+    backlog: VecDeque<Message<Rq, Rs>>,
+    last_activity: Instant,
+    stats: Arc<ConnectionStats>,
+    next_request_id: i64,
+    malformed_packets: usize,
+    limits: ConnectionLimits,
+    map_incoming: Option<IncomingMap<Rq, Rs>>,
+    tags: HashMap<String, String>,
+    /// The span the handler runs in, which `set_tag` records the tags on
+    span: tracing::Span,
+    marker: PhantomData<(Rq, Rs)>,
+}
+
+impl<Rq, Rs> AsyncServerConnection<Rq, Rs>
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    fn new(
+        reader: Reader,
+        writer: Writer,
+        addr: SocketAddr,
+        shutdown: CancellationToken,
+        stats: Arc<ConnectionStats>,
+        limits: ConnectionLimits,
+        map_incoming: Option<IncomingMap<Rq, Rs>>,
+    ) -> Self {
+        Self {
+            addr,
+            reader,
+            buffer: PacketBuffer::new(),
+            writer,
+            shutdown,
+            backlog: VecDeque::new(),
+            last_activity: Instant::now(),
+            stats,
+            next_request_id: 0,
+            malformed_packets: 0,
+            limits,
+            map_incoming,
+            tags: HashMap::new(),
+            span: tracing::Span::none(),
+            marker: PhantomData,
+        }
+    }
+
+    /// A connection that does not belong to any `Server`, see `testing::MockClientConnection`
+    pub(crate) fn standalone(reader: Reader, writer: Writer) -> Self {
+        Self::new(
+            reader,
+            writer,
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            CancellationToken::new(),
+            Arc::new(ConnectionStats::new()),
+            ConnectionLimits::default(),
+            None,
+        )
+    }
+
+    /// Cancelled when the server shuts down
+    pub fn shutdown_token(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    /// When a packet was last sent to or received from the client, for implementing idle timeouts
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
+    /// Attaches metadata, like a user or tenant id, to the connection.
+    /// Tags show up in `Server::connected_clients` and on the handler's tracing span
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        self.tags.insert(key.to_string(), value.to_string());
+        self.stats.tags.lock().unwrap().clone_from(&self.tags);
+        self.span.record("tags", tracing::field::debug(&self.tags));
+    }
+
+    pub fn get_tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    /// Messages that arrived while waiting on a response to `request`, which `recv` returns first
+    pub fn inbound_pending(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// The same snapshot `Server::connected_clients` reports for this connection
+    pub fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            shutdown_requested: self.shutdown.is_cancelled(),
+            ..self.stats.info()
+        }
+    }
+
+    pub(crate) async fn write<T>(&mut self, typ: &T) -> std::io::Result<()>
+    where
+        T: serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug,
+    {
+        TcpPacket::async_write(&mut self.writer, typ).await?;
+        self.last_activity = Instant::now();
+        self.stats.messages_sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Waits for the next message from the client.
+    /// Returns `None` once the client has disconnected or the server is shutting down.
+    /// Malformed packets are answered with an error response and skipped, until there are too
+    /// many of them. Messages dropped by `Server::map_incoming` are skipped too
+    pub async fn recv(&mut self) -> std::io::Result<Option<Message<Rq, Rs>>> {
+        match self.recv_until(std::future::pending::<()>()).await? {
+            Recv::Message(msg) => Ok(Some(msg)),
+            Recv::Closed | Recv::Cancelled(()) => Ok(None),
+        }
+    }
+
+    /// `recv`, but stops waiting once `cancel` completes. Only reading races against `cancel`,
+    /// so a reply to a malformed packet is never cut off partway
+    async fn recv_until<F>(&mut self, cancel: F) -> std::io::Result<Recv<Rq, Rs, F::Output>>
+    where
+        F: Future,
+    {
+        tokio::pin!(cancel);
+        loop {
+            let read = tokio::select! {
+                output = &mut cancel => return Ok(Recv::Cancelled(output)),
+                read = self.read_incoming() => read?,
+            };
+            match read {
+                PacketRead::Message(msg) => return Ok(Recv::Message(msg)),
+                PacketRead::Malformed { payload, reason } => {
+                    self.reply_malformed(&payload, reason).await?
+                }
+                PacketRead::Disconnected | PacketRead::Empty => return Ok(Recv::Closed),
+            }
+        }
+    }
+
+    /// The next message from the backlog or the client, passed through `map_incoming`.
+    /// Never writes, so it is cancel safe. `PacketRead::Disconnected` once the client has
+    /// disconnected or the server is shutting down
+    async fn read_incoming(&mut self) -> std::io::Result<PacketRead<Message<Rq, Rs>>> {
+        loop {
+            let msg = match self.backlog.pop_front() {
+                Some(msg) => {
+                    self.stats.set_inbound_pending(self.backlog.len());
+                    msg
+                }
+                None => {
+                    let read = tokio::select! {
+                        _ = self.shutdown.cancelled() => return Ok(PacketRead::Disconnected),
+                        read = TcpPacket::async_read_buffered(&mut self.reader, &mut self.buffer) => read?,
+                    };
+                    self.last_activity = Instant::now();
+                    let PacketRead::Message(msg) = read else {
+                        return Ok(read);
+                    };
+                    self.stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                    msg
+                }
+            };
+            match &self.map_incoming {
+                Some(map) => {
+                    if let Some(msg) = map(msg) {
+                        return Ok(PacketRead::Message(msg));
+                    }
+                }
+                None => return Ok(PacketRead::Message(msg)),
+            }
+        }
+    }
+
+    /// `recv`, but fails with an `ErrorCode::Timeout` error if nothing arrives within `timeout`.
+    /// `Ok(None)` still means the client disconnected or the server is shutting down
+    pub async fn recv_or_shutdown(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Message<Rq, Rs>>, Error> {
+        match self.recv_until(tokio::time::sleep(timeout)).await? {
+            Recv::Message(msg) => Ok(Some(msg)),
+            Recv::Closed => Ok(None),
+            Recv::Cancelled(()) => Err(Error::new(
+                ErrorCode::Timeout,
+                format!("no message from {} in {timeout:?}", self.addr),
+            )),
+        }
+    }
+
+    async fn reply_malformed(&mut self, payload: &[u8], reason: String) -> std::io::Result<()> {
+        self.malformed_packets += 1;
+        tracing::warn!(
+            "{} sent malformed packet #{}: {reason}",
+            self.addr,
+            self.malformed_packets
+        );
+        self.write(&malformed_response(payload, reason)).await?;
+
+        if self.malformed_packets > self.limits.max_malformed_packets {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} sent too many malformed packets", self.addr),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn send(&mut self, msg: &Message<Rq, Rs>) -> std::io::Result<()> {
+        self.write(msg).await
+    }
+
+    /// Sends a hand made request, bypassing `Rq` and `RpcRequest`.
+    /// Meant for debugging and testing against other JSON-RPC peers, avoid in production code
+    pub async fn send_raw_request(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+        id: impl Into<RequestId>,
+    ) -> std::io::Result<()> {
+        let req = Request {
+            jsonrpc: JSONRPC_FIELD.to_string(),
+            method: method.to_string(),
+            params,
+            id: id.into(),
+        };
+        self.write(&req).await
+    }
+
+    /// Sends a plain JSON-RPC response without an `IdentifiedResponse` identity.
+    /// Meant for debugging and testing against other JSON-RPC peers, avoid in production code
+    pub async fn send_raw_response(
+        &mut self,
+        id: impl Into<RequestId>,
+        result: serde_json::Value,
+    ) -> std::io::Result<()> {
+        let res = Response {
+            jsonrpc: JSONRPC_FIELD.to_string(),
+            result: Some(result),
+            error: None,
+            id: id.into(),
+        };
+        self.write(&res).await
+    }
+
+    /// Forwards every message from the client to `upstream` and every message from `upstream`
+    /// back to the client, until either side disconnects. Call it before `initialize` so the
+    /// client's handshake reaches the upstream server
+    pub async fn proxy_to(&mut self, upstream: TcpStream) -> ServerHandlerResult {
+        let (mut up_reader, mut up_writer) = upstream.into_split();
+        let mut up_buffer = PacketBuffer::new();
+        loop {
+            tokio::select! {
+                read = self.read_incoming() => {
+                    match read? {
+                        PacketRead::Message(msg) => {
+                            TcpPacket::async_write(&mut up_writer, &msg).await?
+                        }
+                        PacketRead::Malformed { payload, reason } => {
+                            self.reply_malformed(&payload, reason).await?;
+                        }
+                        PacketRead::Disconnected | PacketRead::Empty => break,
+                    }
+                }
+                read = TcpPacket::<Message<Rq, Rs>>::async_read_buffered(&mut up_reader, &mut up_buffer) => {
+                    match read? {
+                        PacketRead::Message(msg) => self.send(&msg).await?,
+                        PacketRead::Malformed { reason, .. } => {
+                            tracing::warn!("upstream of {} sent a malformed packet: {reason}", self.addr);
+                        }
+                        PacketRead::Disconnected | PacketRead::Empty => break,
+                    }
+                }
+            }
+        }
+        up_writer.shutdown().await?;
+        Ok(())
+    }
+
+    /// Sends `req` to the client and waits up to `timeout` for its answer.
+    /// Messages the client sends in the meantime are kept for `recv`
+    pub async fn request<R>(
+        &mut self,
+        req: R,
+        timeout: Duration,
+    ) -> Result<Result<R::Response, Error>, RequestError>
+    where
+        R: RpcRequest,
+    {
+        let id = RequestId::from(self.next_request_id);
+        self.next_request_id += 1;
+        let request = req
+            .into_request(id.clone())
+            .map_err(RequestError::Serialize)?;
+        self.write(&request).await?;
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.response_to::<R::Response>(&id, deadline).await
+    }
+
+    /// Only reading races against `deadline`, so replies to malformed packets are written whole
+    async fn response_to<R>(
+        &mut self,
+        id: &RequestId,
+        deadline: tokio::time::Instant,
+    ) -> Result<Result<R, Error>, RequestError>
+    where
+        R: RpcResponse,
+    {
+        loop {
+            let read = tokio::select! {
+                _ = self.shutdown.cancelled() => return Err(RequestError::Disconnected),
+                _ = tokio::time::sleep_until(deadline) => return Err(RequestError::Timeout),
+                read = TcpPacket::<serde_json::Value>::async_read_buffered(&mut self.reader, &mut self.buffer) => read?,
+            };
+            self.last_activity = Instant::now();
+            let json = match read {
+                PacketRead::Message(json) => {
+                    self.stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                    json
+                }
+                PacketRead::Disconnected | PacketRead::Empty => {
+                    return Err(RequestError::Disconnected)
+                }
+                PacketRead::Malformed { payload, reason } => {
+                    self.reply_malformed(&payload, reason).await?;
+                    continue;
+                }
+            };
+
+            if let Ok(res) = serde_json::from_value::<IdentifiedResponse>(json.clone()) {
+                if &res.res.id == id {
+                    return res
+                        .try_into_res::<R>()
+                        .map_err(RequestError::InvalidResponse);
+                }
+            } else if json.get("method").is_none() {
+                // a plain JSON-RPC response, or an error, which is always sent without an identity
+                if let Ok(res) = serde_json::from_value::<Response>(json.clone()) {
+                    if &res.id == id {
+                        return R::try_from_response(&res).map_err(RequestError::InvalidResponse);
+                    }
+                }
+            }
+
+            match serde_json::from_value::<Message<Rq, Rs>>(json.clone()) {
+                Ok(msg) => {
+                    self.backlog.push_back(msg);
+                    self.stats.set_inbound_pending(self.backlog.len());
+                }
+                Err(err) => {
+                    let payload = serde_json::to_vec(&json).unwrap_or_default();
+                    self.reply_malformed(&payload, err.to_string()).await?;
+                }
+            }
+        }
+    }
+
+    /// Waits for the client to send `I`, answering it with `response`.
+    /// Any other message received first is answered with an uninitialized error
+    pub async fn initialize<I>(&mut self, response: I::Response) -> Result<I, Error>
+    where
+        I: RpcRequest,
+    {
+        self.initialize_until(response, std::future::pending::<()>())
+            .await
+    }
+
+    /// Like `initialize`, but fails with an `ErrorCode::Aborted` error if `cancel` completes
+    /// first, such as `tokio::signal::ctrl_c()`. Only reading is cancelled, so a request or
+    /// malformed packet that has been read is always answered, and the connection can be closed
+    /// as usual
+    pub async fn initialize_until<I, F>(
+        &mut self,
+        response: I::Response,
+        cancel: F,
+    ) -> Result<I, Error>
+    where
+        I: RpcRequest,
+        F: Future,
+    {
+        self.stats.is_initializing.store(true, Ordering::Relaxed);
+        let init = self
+            .wait_for_initialize(response, cancel)
+            .await
+            .context("initialize");
+        self.stats.is_initializing.store(false, Ordering::Relaxed);
+        init
+    }
+
+    async fn wait_for_initialize<I, F>(
+        &mut self,
+        response: I::Response,
+        cancel: F,
+    ) -> Result<I, Error>
+    where
+        I: RpcRequest,
+        F: Future,
+    {
+        tokio::pin!(cancel);
+        loop {
+            let msg = match self
+                .recv_until(cancel.as_mut())
+                .await
+                .context("waiting for initialize request")?
+            {
+                Recv::Message(msg) => msg,
+                Recv::Closed => {
+                    return Err(Error::from(ErrorKind::Disconnect)
+                        .context("waiting for initialize request"))
+                }
+                Recv::Cancelled(_) => {
+                    return Err(Error::new(
+                        ErrorCode::Aborted,
+                        "cancelled before the client initialized",
+                    ))
+                }
+            };
+
+            match answer_initialize(&msg, &response, self.limits.max_error_payload)? {
+                InitializeAnswer::Initialized(init, res) => {
+                    self.write(&res)
+                        .await
+                        .context("sending initialize response")?;
+                    return Ok(init);
+                }
+                InitializeAnswer::Uninitialized(err) => {
+                    tracing::warn!("{} sent a message before initializing", self.addr);
+                    self.write(&err)
+                        .await
+                        .context("answering uninitialized client")?;
+                }
+            }
+        }
+    }
+}
+
+/// The error response to a packet that isn't a message: invalid request if the payload is
+/// JSON, parse error if it isn't. Shared by every transport
+pub(crate) fn malformed_response(payload: &[u8], reason: String) -> Response {
+    // A payload that is valid JSON just isn't a message we understand
+    let mut err = match serde_json::from_slice::<serde_json::Value>(payload) {
+        Ok(_) => Error::invalid_request(&reason),
+        Err(_) => Error::parse_error(&reason),
+    };
+    err.data = Some(serde_json::json!({ "reason": reason }));
+    Response::parse_error_null_id(err)
+}
+
+/// What a server waiting for the client to initialize sends back, see `answer_initialize`
+pub(crate) enum InitializeAnswer<I> {
+    Initialized(I, IdentifiedResponse),
+    Uninitialized(Response),
+}
+
+/// Answers `msg` with `response` if it is the initialize request `I`, and with an uninitialized
+/// error echoing at most `max_error_payload` bytes of it otherwise. Shared by every transport
+pub(crate) fn answer_initialize<I, Rq, Rs>(
+    msg: &Message<Rq, Rs>,
+    response: &I::Response,
+    max_error_payload: usize,
+) -> Result<InitializeAnswer<I>, Error>
+where
+    I: RpcRequest,
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+{
+    if let Message::Req { id, req } = msg {
+        if let Ok(init) = I::try_from_request(&req.into_req(id)) {
+            let res = IdentifiedResponse::from_res(id, response).map_err(|err| {
+                Error::internal(format!("failed to serialize initialize response: {err}"))
+            })?;
+            return Ok(InitializeAnswer::Initialized(init, res));
+        }
+    }
+    Ok(InitializeAnswer::Uninitialized(Response::from_error(
+        msg.id(),
+        ErrorKind::uninitialized_with_limit(msg, max_error_payload).into(),
+    )))
+}
@@ -0,0 +1,140 @@
+//! The WebSocket transport behind `Server::listen_ws` and `Connection::connect_ws`.
+//! Each message is one text frame holding its JSON. A bridge task turns frames into packets
+//! on an in-memory stream, so connections read and write it like they would a socket
+use crate::{packet::PacketBuffer, SeraphicError};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Error as WsError, Message as Frame,
+    },
+    WebSocketStream,
+};
+
+/// How many bytes of packets the bridge holds in each direction
+const BRIDGE_CAPACITY: usize = 64 * 1024;
+
+fn ws_error(err: WsError) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+/// A peer that already closed the connection has nothing left to be told
+fn ignore_closed(result: Result<(), WsError>) -> std::io::Result<()> {
+    match result {
+        Ok(()) | Err(WsError::ConnectionClosed | WsError::AlreadyClosed) => Ok(()),
+        Err(err) => Err(ws_error(err)),
+    }
+}
+
+/// Performs the server side of the handshake, refusing any path other than `path`
+// the handshake callback's error type is tungstenite's, not ours to shrink
+#[allow(clippy::result_large_err)]
+pub(crate) async fn accept(stream: TcpStream, path: &str) -> std::io::Result<DuplexStream> {
+    let check_path = |req: &Request, res: Response| {
+        if req.uri().path() == path {
+            return Ok(res);
+        }
+        let mut res = ErrorResponse::new(None);
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        Err(res)
+    };
+    let ws = tokio_tungstenite::accept_hdr_async(stream, check_path)
+        .await
+        .map_err(ws_error)?;
+    Ok(bridge(ws))
+}
+
+pub(crate) async fn connect(url: &str) -> Result<DuplexStream, SeraphicError> {
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(ws_error)?;
+    Ok(bridge(ws))
+}
+
+fn bridge<S>(ws: WebSocketStream<S>) -> DuplexStream
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (local, remote) = tokio::io::duplex(BRIDGE_CAPACITY);
+    let (packets_in, packets_out) = tokio::io::split(remote);
+    let (frames_out, frames_in) = ws.split();
+    tokio::spawn(async move {
+        // each direction on its own, so one waiting on the other end can't stall the other
+        let (read, write) = tokio::join!(
+            frames_to_packets(frames_in, packets_out),
+            packets_to_frames(packets_in, frames_out)
+        );
+        if let Err(err) = read.and(write) {
+            tracing::debug!("websocket bridge stopped: {err}");
+        }
+    });
+    local
+}
+
+async fn frames_to_packets<S>(
+    mut frames: futures_util::stream::SplitStream<WebSocketStream<S>>,
+    mut packets: WriteHalf<DuplexStream>,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let forwarded = async {
+        while let Some(frame) = frames.next().await {
+            let payload = match frame.map_err(ws_error)? {
+                Frame::Text(text) => text.into(),
+                Frame::Binary(bytes) => bytes,
+                // pings are answered by tungstenite itself
+                Frame::Ping(_) | Frame::Pong(_) | Frame::Frame(_) => continue,
+                Frame::Close(_) => break,
+            };
+            let size = u32::try_from(payload.len()).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "frame is too large")
+            })?;
+            packets.write_all(&size.to_le_bytes()).await?;
+            packets.write_all(&payload).await?;
+        }
+        Ok(())
+    }
+    .await;
+    // the connection reads this as the peer disconnecting, however the socket went away
+    packets.shutdown().await?;
+    forwarded
+}
+
+async fn packets_to_frames<S>(
+    mut packets: ReadHalf<DuplexStream>,
+    mut frames: futures_util::stream::SplitSink<WebSocketStream<S>, Frame>,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = PacketBuffer::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let read = packets.read(&mut chunk).await?;
+        if read == 0 {
+            // the connection was dropped or shut down. Saying so with a Close frame, rather than
+            // just hanging up, lets the peer tell this apart from the connection breaking
+            let close = Frame::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: "".into(),
+            }));
+            ignore_closed(frames.send(close).await)?;
+            return ignore_closed(frames.close().await);
+        }
+        buffer.extend(&chunk[..read]);
+        while let Some(payload) = buffer.next_payload() {
+            let frame = match String::from_utf8(payload) {
+                Ok(text) => Frame::text(text),
+                Err(err) => Frame::binary(err.into_bytes()),
+            };
+            frames.send(frame).await.map_err(ws_error)?;
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Answering requests with a [`tower_service::Service`], so tower middleware such as timeouts
+//! and rate limits can wrap a handler
+use crate::{
+    error::Error,
+    tokio::{AsyncServerConnection, ServerHandlerResult},
+    MainErr, Message, Request, RequestWrapper, Response, ResponseWrapper,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+pub use tower_service::Service;
+
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response, Error>> + Send>>;
+
+/// A `Service<Request>` made from an async function
+#[derive(Debug, Clone)]
+pub struct SeraphicService<F> {
+    f: F,
+}
+
+impl<F, Fut> SeraphicService<F>
+where
+    F: FnMut(Request) -> Fut,
+    Fut: Future<Output = Result<Response, Error>> + Send + 'static,
+{
+    pub fn new(f: F) -> Self {
+        Self { f }
+    }
+}
+
+impl<F, Fut> Service<Request> for SeraphicService<F>
+where
+    F: FnMut(Request) -> Fut,
+    Fut: Future<Output = Result<Response, Error>> + Send + 'static,
+{
+    type Response = Response;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        Box::pin((self.f)(req))
+    }
+}
+
+/// Errors added by middleware, like tower's timeout, become internal errors
+pub(crate) fn into_rpc_error(err: MainErr) -> Error {
+    match err.downcast::<Error>() {
+        Ok(err) => *err,
+        Err(err) => Error::internal(err),
+    }
+}
+
+/// Answers every request the client sends with `service`, until the client disconnects or the
+/// server shuts down. Responses are plain JSON-RPC responses, without an identity.
+/// An error from `service` is sent back as an error response, so `service` can be wrapped in
+/// any tower middleware. Anything other than a request is ignored
+pub async fn serve<Rq, Rs, S>(
+    conn: &mut AsyncServerConnection<Rq, Rs>,
+    mut service: S,
+) -> ServerHandlerResult
+where
+    Rq: RequestWrapper,
+    Rs: ResponseWrapper,
+    S: Service<Request, Response = Response>,
+    S::Error: Into<MainErr>,
+{
+    while let Some(msg) = conn.recv().await? {
+        let Message::Req { id, req } = msg else {
+            continue;
+        };
+        std::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .map_err(Into::into)?;
+        let res = match service.call(req.into_req(&id)).await {
+            Ok(res) => res,
+            Err(err) => Response::from_error(id, into_rpc_error(err.into())),
+        };
+        conn.write(&res).await?;
+    }
+    Ok(())
+}
@@ -13,7 +13,8 @@ struct TestData {
 
 #[tokio::test]
 async fn test_async_tcp_packet_read_write() {
-    let listener = TcpListener::bind("127.0.0.1:7879").await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
 
     tokio::spawn(async move {
         let (socket, _) = listener.accept().await.unwrap();
@@ -30,7 +31,7 @@ async fn test_async_tcp_packet_read_write() {
 
     sleep(Duration::from_millis(100));
 
-    let mut stream = TcpStream::connect("127.0.0.1:7879").await.unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
     let test_data = TestData {
         id: 42,
         message: "Async Hello".into(),
@@ -39,3 +40,145 @@ async fn test_async_tcp_packet_read_write() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_async_write_batch() {
+    let messages: Vec<TestData> = (0..100)
+        .map(|id| TestData {
+            id,
+            message: format!("message {id}"),
+        })
+        .collect();
+
+    let mut stream = vec![];
+    TcpPacket::async_write_batch(&mut stream, &messages)
+        .await
+        .unwrap();
+
+    let mut reader = stream.as_slice();
+    for expected in messages {
+        let read: PacketRead<TestData> = TcpPacket::async_read(&mut reader).await.unwrap();
+        assert_eq!(read, PacketRead::Message(expected));
+    }
+    assert_eq!(
+        TcpPacket::<TestData>::async_read(&mut reader)
+            .await
+            .unwrap(),
+        PacketRead::Disconnected
+    );
+}
+
+#[tokio::test]
+async fn async_read_malformed() {
+    let data = TestData {
+        id: 1,
+        message: "Hello".into(),
+    };
+    let mut stream = vec![2, 0, 0, 0, b'{', b'}'];
+    TcpPacket::async_write(&mut stream, &data).await.unwrap();
+
+    let mut reader = stream.as_slice();
+    assert!(matches!(
+        TcpPacket::<TestData>::async_read(&mut reader).await.unwrap(),
+        PacketRead::Malformed { payload, .. } if payload == b"{}"
+    ));
+    assert_eq!(
+        TcpPacket::<TestData>::async_read(&mut reader)
+            .await
+            .unwrap(),
+        PacketRead::Message(data)
+    );
+}
+
+/// Writes the first half of `packet`, then the rest after `pause`
+fn send_in_halves(
+    mut peer: tokio::io::DuplexStream,
+    packet: Vec<u8>,
+    pause: Duration,
+) -> tokio::task::JoinHandle<tokio::io::DuplexStream> {
+    use tokio::io::AsyncWriteExt;
+    tokio::spawn(async move {
+        let (first, rest) = packet.split_at(packet.len() / 2);
+        peer.write_all(first).await.unwrap();
+        tokio::time::sleep(pause).await;
+        peer.write_all(rest).await.unwrap();
+        peer
+    })
+}
+
+#[tokio::test]
+async fn async_read_timeout_idle_and_frame() {
+    use seraphic::{packet::PacketBuffer, tokio::ReadTimeouts};
+
+    let timeouts = ReadTimeouts {
+        idle: Some(Duration::from_millis(100)),
+        frame: Some(Duration::from_millis(50)),
+    };
+    let data = TestData {
+        id: 7,
+        message: "slow".into(),
+    };
+    let mut packet = vec![];
+    TcpPacket::async_write(&mut packet, &data).await.unwrap();
+
+    let (peer, mut reader) = tokio::io::duplex(1024);
+    let mut buffer = PacketBuffer::new();
+    assert_eq!(
+        TcpPacket::<TestData>::async_read_timeout(&mut reader, &mut buffer, timeouts)
+            .await
+            .unwrap(),
+        PacketRead::Empty
+    );
+    // a pause the idle timeout would allow, but the frame timeout doesn't
+    let peer = send_in_halves(peer, packet.clone(), Duration::from_millis(75));
+    let err = TcpPacket::<TestData>::async_read_timeout(&mut reader, &mut buffer, timeouts)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    peer.await.unwrap();
+
+    let (peer, mut reader) = tokio::io::duplex(1024);
+    let mut buffer = PacketBuffer::new();
+    let peer = send_in_halves(peer, packet, Duration::from_millis(75));
+    let timeouts = ReadTimeouts {
+        frame: Some(Duration::from_secs(5)),
+        ..timeouts
+    };
+    assert_eq!(
+        TcpPacket::async_read_timeout(&mut reader, &mut buffer, timeouts)
+            .await
+            .unwrap(),
+        PacketRead::Message(data)
+    );
+    peer.await.unwrap();
+}
+
+#[tokio::test]
+async fn async_read_timeout_frame_survives_cancellation() {
+    use seraphic::{packet::PacketBuffer, tokio::ReadTimeouts};
+    use tokio::io::AsyncWriteExt;
+
+    let timeouts = ReadTimeouts {
+        idle: None,
+        frame: Some(Duration::from_millis(100)),
+    };
+    let (mut peer, mut reader) = tokio::io::duplex(1024);
+    let mut buffer = PacketBuffer::new();
+    // half a header, and then nothing
+    peer.write_all(&[1, 0]).await.unwrap();
+
+    // every read gives up well before the frame timeout, as a `select!` loop would
+    let err = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let read =
+                TcpPacket::<TestData>::async_read_timeout(&mut reader, &mut buffer, timeouts);
+            if let Ok(result) = tokio::time::timeout(Duration::from_millis(30), read).await {
+                return result.unwrap_err();
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    drop(peer);
+}
@@ -0,0 +1,954 @@
+use super::*;
+use seraphic::{
+    error::{Error, ErrorCode},
+    packet::{PacketRead, TcpPacket},
+    tokio::{AsyncServerConnection, Server, ServerConnectionHandler, ServerHandlerResult},
+    Request, RequestId, RequestWrapper,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+pub struct EchoHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for EchoHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        echo(conn).await
+    }
+}
+
+async fn echo(conn: &mut AsyncServerConnection<MyRequest, MyResponse>) -> ServerHandlerResult {
+    while let Some(msg) = conn.recv().await? {
+        if let Message::Req {
+            id,
+            req: MyRequest::Foo(_),
+        } = msg
+        {
+            let res = MyResponse::from(FooResponse {}).into_message(id);
+            conn.send(&res).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn send(stream: &mut TcpStream, msg: Message) {
+    MessagePacket::async_write(stream, &msg).await.unwrap();
+}
+
+pub async fn recv(stream: &mut TcpStream) -> Message {
+    match MessagePacket::async_read(stream).await.unwrap() {
+        PacketRead::Message(msg) => msg,
+        other => panic!("expected a message, got: {other:#?}"),
+    }
+}
+
+#[tokio::test]
+async fn async_server_initialize_and_echo() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_token();
+    let server = tokio::spawn(async move {
+        server.run().await.unwrap();
+        server.shutdown_and_join_all_connections().await;
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    send(&mut stream, MyRequest::from(FooRequest {}).into_message(0)).await;
+    match recv(&mut stream).await {
+        Message::Err { id, err } => {
+            assert_eq!(id, RequestId::from(0));
+            assert_eq!(
+                err.code,
+                ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START)
+            );
+        }
+        other => panic!("expected uninitialized error, got: {other:#?}"),
+    }
+
+    send(&mut stream, MyRequest::from(TestRequest {}).into_message(1)).await;
+    assert_eq!(
+        recv(&mut stream).await,
+        MyResponse::from(TestResponse {}).into_message(1)
+    );
+
+    send(&mut stream, MyRequest::from(FooRequest {}).into_message(2)).await;
+    assert_eq!(
+        recv(&mut stream).await,
+        MyResponse::from(FooResponse {}).into_message(2)
+    );
+
+    shutdown.cancel();
+    server.await.unwrap();
+    assert_eq!(
+        MessagePacket::async_read(&mut stream).await.unwrap(),
+        PacketRead::Disconnected
+    );
+}
+
+async fn send_raw(stream: &mut TcpStream, payload: &[u8]) {
+    stream
+        .write_all(&(payload.len() as u32).to_le_bytes())
+        .await
+        .unwrap();
+    stream.write_all(payload).await.unwrap();
+}
+
+fn expect_err(msg: Message, code: ErrorCode) {
+    match msg {
+        Message::Err { err, .. } => assert_eq!(err.code, code),
+        other => panic!("expected {code:?} error, got: {other:#?}"),
+    }
+}
+
+#[tokio::test]
+async fn async_server_caps_uninitialized_payload() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_max_error_payload(16);
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(TcpStream::connect(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let mut stream = stream.unwrap();
+
+    send(&mut stream, MyRequest::from(FooRequest {}).into_message(0)).await;
+    match recv(&mut stream).await {
+        Message::Err { err, .. } => {
+            let data = err.data.unwrap();
+            assert_eq!(data["truncated"], true);
+            assert_eq!(data["payload"].as_str().unwrap().len(), 16);
+        }
+        other => panic!("expected uninitialized error, got: {other:#?}"),
+    }
+    drop(stream);
+    server.shutdown_and_join_all_connections().await;
+}
+
+#[tokio::test]
+async fn async_server_survives_malformed_packets() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_max_malformed_packets(2);
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_token();
+    let server = tokio::spawn(async move {
+        server.run().await.unwrap();
+        server.shutdown_and_join_all_connections().await;
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    send_raw(&mut stream, b"{not json").await;
+    let PacketRead::Message(res) = TcpPacket::<serde_json::Value>::async_read(&mut stream)
+        .await
+        .unwrap()
+    else {
+        panic!("expected a response");
+    };
+    assert_eq!(res["id"], serde_json::Value::Null);
+    assert_eq!(res["error"]["code"], -32700);
+    assert!(res.get("result").is_none());
+
+    send_raw(&mut stream, br#"{"jsonrpc":"2.0","method":"nope"}"#).await;
+    expect_err(recv(&mut stream).await, ErrorCode::InvalidRequest);
+
+    send(&mut stream, MyRequest::from(TestRequest {}).into_message(1)).await;
+    assert_eq!(
+        recv(&mut stream).await,
+        MyResponse::from(TestResponse {}).into_message(1)
+    );
+
+    // one more than the threshold drops the connection
+    send_raw(&mut stream, b"garbage").await;
+    expect_err(recv(&mut stream).await, ErrorCode::ParseError);
+    assert_eq!(
+        MessagePacket::async_read(&mut stream).await.unwrap(),
+        PacketRead::Disconnected
+    );
+
+    shutdown.cancel();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn async_server_relisten_on_same_port() {
+    let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    for _ in 0..2 {
+        let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen(addr)
+            .await
+            .unwrap();
+        // the second server binds the port the first one was given
+        addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_token();
+        let server = tokio::spawn(async move {
+            server.run().await.unwrap();
+            server.shutdown_and_join_all_connections().await;
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        send(&mut stream, MyRequest::from(TestRequest {}).into_message(1)).await;
+        assert_eq!(
+            recv(&mut stream).await,
+            MyResponse::from(TestResponse {}).into_message(1)
+        );
+
+        // the server closes first, leaving its side of the socket in TIME_WAIT
+        shutdown.cancel();
+        server.await.unwrap();
+    }
+}
+
+pub async fn connect_and_initialize(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    send(&mut stream, MyRequest::from(TestRequest {}).into_message(0)).await;
+    assert_eq!(
+        recv(&mut stream).await,
+        MyResponse::from(TestResponse {}).into_message(0)
+    );
+    stream
+}
+
+#[tokio::test]
+async fn async_server_concurrent_connections() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (mut a, mut b, c, ()) = tokio::join!(
+        connect_and_initialize(addr),
+        connect_and_initialize(addr),
+        connect_and_initialize(addr),
+        async {
+            for _ in 0..3 {
+                server.next().await.unwrap().unwrap();
+            }
+        }
+    );
+    let [a_addr, b_addr, c_addr] = [&a, &b, &c].map(|s| s.local_addr().unwrap());
+    assert!([a_addr, b_addr, c_addr]
+        .iter()
+        .all(|addr| server.is_connected(addr)));
+
+    // only a is told to shut down
+    assert!(server.disconnect(&a_addr, true));
+    assert!(server.join_connection(&a_addr).await.unwrap().is_ok());
+    assert!(!server.is_connected(&a_addr));
+    assert!(server.join_connection(&a_addr).await.is_none());
+    assert_eq!(
+        MessagePacket::async_read(&mut a).await.unwrap(),
+        PacketRead::Disconnected
+    );
+
+    send(&mut b, MyRequest::from(FooRequest {}).into_message(1)).await;
+    assert_eq!(
+        recv(&mut b).await,
+        MyResponse::from(FooResponse {}).into_message(1)
+    );
+
+    // b hanging up ends its handler
+    drop(b);
+    assert!(server.join_connection(&b_addr).await.unwrap().is_ok());
+
+    assert!(server.disconnect(&c_addr, false));
+    assert!(server.join_connection(&c_addr).await.unwrap().is_err());
+    drop(c);
+
+    server.shutdown_and_join_all_connections().await;
+}
+
+#[tokio::test]
+async fn async_server_reaps_finished_handlers() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (a, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let a_addr = a.local_addr().unwrap();
+    drop(a);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while server.is_connected(&a_addr) {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // accepting b forgets a's finished handler without anyone joining it
+    let (b, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let b_addr = b.local_addr().unwrap();
+    assert!(server.join_connection(&a_addr).await.is_none());
+    assert_eq!(
+        server.connected_clients().keys().collect::<Vec<_>>(),
+        [&b_addr]
+    );
+
+    drop(b);
+    assert!(server.join_connection(&b_addr).await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn async_server_reports_every_handler_result() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (ok, failing, ()) = tokio::join!(
+        connect_and_initialize(addr),
+        TcpStream::connect(addr),
+        async {
+            for _ in 0..2 {
+                server.next().await.unwrap().unwrap();
+            }
+        }
+    );
+    let ok_addr = ok.local_addr().unwrap();
+    let failing = failing.unwrap();
+    let failing_addr = failing.local_addr().unwrap();
+    // hanging up before initializing makes the handler return an error
+    drop(failing);
+    while server.is_connected(&failing_addr) {
+        tokio::task::yield_now().await;
+    }
+
+    let results = server.shutdown_and_join_all_connections().await;
+    assert_eq!(results.len(), 2);
+    for (addr, result) in results {
+        let result = result.expect("handler should not panic");
+        if addr == ok_addr {
+            assert!(result.is_ok());
+        } else {
+            assert_eq!(addr, failing_addr);
+            let err = result.unwrap_err().downcast::<Error>().unwrap();
+            assert_eq!(err.code, ErrorCode::Disconnect);
+            assert_eq!(
+                err.message,
+                "initialize -> waiting for initialize request -> disconnected channel"
+            );
+        }
+    }
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test")]
+pub struct StatusRequest {}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatusResponse {
+    healthy: bool,
+}
+
+pub struct StatusHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for StatusHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        let status = conn
+            .request(StatusRequest {}, Duration::from_secs(5))
+            .await?
+            .unwrap();
+        assert_eq!(status, StatusResponse { healthy: true });
+        assert_eq!(conn.inbound_pending(), 2);
+        echo(conn).await?;
+
+        let info = conn.info();
+        assert_eq!((info.inbound_pending, info.inbound_high_water), (0, 2));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn async_server_requests_client() {
+    let mut server = Server::<MyRequest, MyResponse, StatusHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+
+    let req = match TcpPacket::<Request>::async_read(&mut stream).await.unwrap() {
+        PacketRead::Message(req) => req,
+        other => panic!("expected a request, got: {other:#?}"),
+    };
+    let status = StatusRequest::try_from_request(&req).unwrap();
+    assert_eq!(status, StatusRequest {});
+
+    // the handler gets these once its own request has been answered
+    for id in 1..3 {
+        send(&mut stream, MyRequest::from(FooRequest {}).into_message(id)).await;
+    }
+
+    let res = StatusResponse { healthy: true }
+        .into_response(req.id)
+        .unwrap();
+    TcpPacket::async_write(&mut stream, &res).await.unwrap();
+
+    for id in 1..3 {
+        assert_eq!(
+            recv(&mut stream).await,
+            MyResponse::from(FooResponse {}).into_message(id)
+        );
+    }
+
+    drop(stream);
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+const IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+pub struct IdleHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for IdleHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        let connected = conn.last_activity();
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        assert!(conn.last_activity() > connected);
+        loop {
+            match conn.recv_or_shutdown(IDLE_TIMEOUT).await {
+                Ok(Some(msg)) => {
+                    let res = MyResponse::from(FooResponse {}).into_message(msg.id().clone());
+                    conn.send(&res).await?;
+                }
+                Ok(None) => return Ok(()),
+                Err(err) if err.code == ErrorCode::Timeout => {
+                    if conn.last_activity().elapsed() >= IDLE_TIMEOUT {
+                        return Ok(());
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn async_server_idle_timeout() {
+    let mut server = Server::<MyRequest, MyResponse, IdleHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+
+    // staying active keeps the connection open past the timeout
+    for id in 1..5 {
+        tokio::time::sleep(IDLE_TIMEOUT / 4).await;
+        send(&mut stream, MyRequest::from(FooRequest {}).into_message(id)).await;
+        assert_eq!(
+            recv(&mut stream).await,
+            MyResponse::from(FooResponse {}).into_message(id)
+        );
+    }
+
+    assert_eq!(
+        MessagePacket::async_read(&mut stream).await.unwrap(),
+        PacketRead::Disconnected
+    );
+    assert!(server.join_connection(&client_addr).await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn async_server_connected_clients() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (mut ready, waiting, ()) = tokio::join!(
+        connect_and_initialize(addr),
+        TcpStream::connect(addr),
+        async {
+            for _ in 0..2 {
+                server.next().await.unwrap().unwrap();
+            }
+        }
+    );
+    let waiting = waiting.unwrap();
+    send(&mut ready, MyRequest::from(FooRequest {}).into_message(1)).await;
+    recv(&mut ready).await;
+
+    let ready_addr = ready.local_addr().unwrap();
+    let waiting_addr = waiting.local_addr().unwrap();
+    while !server.connected_clients()[&waiting_addr].is_initializing {
+        tokio::task::yield_now().await;
+    }
+
+    let clients = server.connected_clients();
+    assert_eq!(clients.len(), 2);
+    let info = &clients[&ready_addr];
+    assert_eq!((info.messages_received, info.messages_sent), (2, 2));
+    assert!(!info.is_initializing);
+    let info = &clients[&waiting_addr];
+    assert_eq!((info.messages_received, info.messages_sent), (0, 0));
+    assert!(info.connected_at.elapsed() < Duration::from_secs(5));
+
+    drop(ready);
+    assert!(server.join_connection(&ready_addr).await.unwrap().is_ok());
+    assert!(!server.connected_clients().contains_key(&ready_addr));
+
+    drop(waiting);
+    server.shutdown_and_join_all_connections().await;
+}
+
+pub struct RawHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for RawHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.send_raw_request("ping", serde_json::json!([1, 2]), "raw")
+            .await?;
+        conn.send_raw_response(3, serde_json::json!({"anything": true}))
+            .await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn async_server_raw_messages() {
+    let mut server = Server::<MyRequest, MyResponse, RawHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(TcpStream::connect(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let mut stream = stream.unwrap();
+
+    let mut read_json = async || match TcpPacket::<serde_json::Value>::async_read(&mut stream)
+        .await
+        .unwrap()
+    {
+        PacketRead::Message(json) => json,
+        other => panic!("expected a message, got: {other:#?}"),
+    };
+    assert_eq!(
+        read_json().await,
+        serde_json::json!({"jsonrpc": "2.0", "method": "ping", "params": [1, 2], "id": "raw"})
+    );
+    assert_eq!(
+        read_json().await,
+        serde_json::json!({"jsonrpc": "2.0", "result": {"anything": true}, "id": 3})
+    );
+    server.shutdown_and_join_all_connections().await;
+}
+
+pub struct StuckHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for StuckHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        // never looks at the shutdown token
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn async_server_join_timeout() {
+    let mut server = Server::<MyRequest, MyResponse, StuckHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+
+    // the stuck handler is given up on at the timeout rather than waited on forever. The upper
+    // bound is loose so a loaded machine doesn't fail the test
+    let start = std::time::Instant::now();
+    let outcome = server
+        .shutdown_and_join_all_connections_timeout(Duration::from_millis(200))
+        .await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(outcome.finished.is_empty());
+    assert_eq!(outcome.abandoned, [client_addr]);
+    assert!(!server.is_connected(&client_addr));
+
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let outcome = server
+        .shutdown_and_join_all_connections_timeout(Duration::from_millis(500))
+        .await;
+    assert!(outcome.abandoned.is_empty());
+    let [(finished, Ok(Ok(())))] = outcome.finished.as_slice() else {
+        panic!("expected the handler to finish, got: {outcome:?}");
+    };
+    assert_eq!(*finished, stream.local_addr().unwrap());
+}
+
+pub struct PanicHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for PanicHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        panic!("handler bug")
+    }
+}
+
+#[tokio::test]
+async fn async_server_handler_panic_is_an_error() {
+    let mut server = Server::<MyRequest, MyResponse, PanicHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+
+    let err = server
+        .join_connection(&client_addr)
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("handler for {client_addr} panicked: handler bug")
+    );
+}
+
+#[tokio::test]
+async fn handler_with_mock_client() {
+    use seraphic::testing::MockClientConnection;
+
+    let (mut client, mut conn) = MockClientConnection::<MyRequest, MyResponse>::pair();
+    let handler = tokio::spawn(async move { EchoHandler::handler(&mut conn).await });
+
+    client
+        .inject(&MyRequest::from(FooRequest {}).into_message(0))
+        .await
+        .unwrap();
+    let Some(Message::Err { id, err }) = client.next_sent().await.unwrap() else {
+        panic!("expected an uninitialized error");
+    };
+    assert_eq!(id, RequestId::from(0));
+    assert_eq!(
+        err.code,
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START)
+    );
+
+    client
+        .inject(&MyRequest::from(TestRequest {}).into_message(1))
+        .await
+        .unwrap();
+    for id in 2..4 {
+        client
+            .inject(&MyRequest::from(FooRequest {}).into_message(id))
+            .await
+            .unwrap();
+    }
+    let sent = tokio::time::timeout(Duration::from_secs(5), client.wait_for_sent(3))
+        .await
+        .expect("handler did not answer")
+        .unwrap();
+    assert_eq!(
+        sent,
+        [
+            MyResponse::from(TestResponse {}).into_message(1),
+            MyResponse::from(FooResponse {}).into_message(2),
+            MyResponse::from(FooResponse {}).into_message(3),
+        ]
+    );
+
+    drop(client);
+    handler.await.unwrap().unwrap();
+}
+
+/// Sends a `FooRequest` and waits for its answer, against whatever is playing the client
+async fn foo_roundtrip(conn: &mut impl seraphic::tokio::ConnectionLike<MyRequest, MyResponse>) {
+    conn.send(MyRequest::from(FooRequest {}).into_message(7))
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        Some(MyResponse::from(FooResponse {}).into_message(7))
+    );
+}
+
+#[tokio::test]
+async fn connection_like_mock_and_client() {
+    use seraphic::{testing::MockClientConnection, tokio::ClientConnection};
+
+    let (mut client, mut conn) = MockClientConnection::<MyRequest, MyResponse>::pair();
+    let handler = tokio::spawn(async move { echo(&mut conn).await });
+    foo_roundtrip(&mut client).await;
+    // nothing else was sent
+    assert!(client.sent_messages().unwrap().is_empty());
+    drop(client);
+    handler.await.unwrap().unwrap();
+
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (client, accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let mut client = client.unwrap();
+    accepted.unwrap().unwrap();
+    client.initialize(TestRequest {}).await.unwrap().unwrap();
+    foo_roundtrip(&mut client).await;
+    client.shutdown(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn async_server_map_incoming() {
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap()
+        .map_incoming(|msg| match msg {
+            // drop requests with id 2, answer 3 as if it were 30
+            Message::Req { id, .. } if id == RequestId::from(2) => None,
+            Message::Req { id, req } if id == RequestId::from(3) => {
+                Some(Message::Req { id: 30.into(), req })
+            }
+            other => Some(other),
+        });
+    let addr = server.local_addr().unwrap();
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+
+    for id in [2, 3] {
+        send(&mut stream, MyRequest::from(FooRequest {}).into_message(id)).await;
+    }
+    assert_eq!(
+        recv(&mut stream).await,
+        MyResponse::from(FooResponse {}).into_message(30)
+    );
+    let clients = server.connected_clients();
+    assert_eq!(clients[&stream.local_addr().unwrap()].messages_received, 3);
+}
+
+static UPSTREAM: std::sync::OnceLock<SocketAddr> = std::sync::OnceLock::new();
+
+pub struct ProxyHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for ProxyHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        let upstream = TcpStream::connect(UPSTREAM.get().unwrap()).await?;
+        conn.proxy_to(upstream).await
+    }
+}
+
+#[tokio::test]
+async fn async_server_proxy() {
+    let mut upstream = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    UPSTREAM.set(upstream.local_addr().unwrap()).unwrap();
+    let upstream = tokio::spawn(async move {
+        let proxy_addr = upstream.next().await.unwrap().unwrap();
+        upstream.join_connection(&proxy_addr).await.unwrap()
+    });
+
+    let mut proxy = Server::<MyRequest, MyResponse, ProxyHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = proxy.local_addr().unwrap();
+
+    // the handshake goes through to the upstream server
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        proxy.next().await.unwrap().unwrap();
+    });
+    for id in 1..3 {
+        send(&mut stream, MyRequest::from(FooRequest {}).into_message(id)).await;
+        assert_eq!(
+            recv(&mut stream).await,
+            MyResponse::from(FooResponse {}).into_message(id)
+        );
+    }
+
+    drop(stream);
+    let client = *proxy.connected_clients().keys().next().unwrap();
+    proxy.join_connection(&client).await.unwrap().unwrap();
+    upstream.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn async_server_connection_status() {
+    let mut server = Server::<MyRequest, MyResponse, StuckHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+    assert!(!server.connected_clients()[&client_addr].shutdown_requested);
+
+    // the handler ignores the token, so it keeps running
+    assert!(server.disconnect(&client_addr, true));
+    assert!(server.connected_clients()[&client_addr].shutdown_requested);
+    assert!(server.try_join_connection(&client_addr).await.is_none());
+    assert!(server.is_connected(&client_addr));
+
+    assert!(server.disconnect(&client_addr, false));
+    let result = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(result) = server.try_join_connection(&client_addr).await {
+                return result;
+            }
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(result.is_err());
+    assert!(server.try_join_connection(&client_addr).await.is_none());
+}
+
+pub struct TaggingHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for TaggingHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        assert_eq!(conn.get_tag("user"), None);
+        conn.set_tag("user", "alice");
+        conn.set_tag("role", "admin");
+        conn.set_tag("role", "guest");
+        assert_eq!(conn.get_tag("role"), Some("guest"));
+        echo(conn).await
+    }
+}
+
+#[tokio::test]
+async fn async_server_connection_tags() {
+    let mut server = Server::<MyRequest, MyResponse, TaggingHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+    let client_addr = stream.local_addr().unwrap();
+
+    // once this is answered the handler has set its tags
+    send(&mut stream, MyRequest::from(FooRequest {}).into_message(1)).await;
+    recv(&mut stream).await;
+
+    let tags = &server.connected_clients()[&client_addr].tags;
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags["user"], "alice");
+    assert_eq!(tags["role"], "guest");
+
+    drop(stream);
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn initialize_until_cancelled() {
+    use seraphic::testing::MockClientConnection;
+
+    let (mut client, mut conn) = MockClientConnection::<MyRequest, MyResponse>::pair();
+    let err = conn
+        .initialize_until::<TestRequest, _>(
+            TestResponse {},
+            tokio::time::sleep(Duration::from_millis(50)),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::Aborted);
+
+    // the lost race didn't take anything off the connection
+    client
+        .inject(&MyRequest::from(TestRequest {}).into_message(1))
+        .await
+        .unwrap();
+    conn.initialize_until::<TestRequest, _>(TestResponse {}, std::future::pending::<()>())
+        .await
+        .unwrap();
+    assert_eq!(
+        client.next_sent().await.unwrap(),
+        Some(MyResponse::from(TestResponse {}).into_message(1))
+    );
+
+    drop(conn);
+    assert_eq!(client.next_sent().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn initialize_until_cancelled_during_malformed_reply() {
+    use seraphic::testing::MockClientConnection;
+
+    // too small for the reply, so writing it waits until the client reads
+    let (mut client, mut conn) =
+        MockClientConnection::<MyRequest, MyResponse>::pair_with_capacity(16);
+    client
+        .inject_bytes(&[2, 0, 0, 0, b'{', b'}'])
+        .await
+        .unwrap();
+    let init = tokio::spawn(async move {
+        let init = conn
+            .initialize_until::<TestRequest, _>(
+                TestResponse {},
+                tokio::time::sleep(Duration::from_millis(50)),
+            )
+            .await;
+        (conn, init)
+    });
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    // the reply still arrives whole
+    let reply = tokio::time::timeout(Duration::from_secs(5), client.next_sent())
+        .await
+        .expect("reply was cut off");
+    let Some(Message::Err { id, err }) = reply.unwrap() else {
+        panic!("expected the malformed packet's error");
+    };
+    assert_eq!(id, RequestId::Null);
+    assert_eq!(err.code, ErrorCode::InvalidRequest);
+    let (mut conn, init) = init.await.unwrap();
+    assert_eq!(init.unwrap_err().code, ErrorCode::Aborted);
+
+    // the connection still initializes, with both ends running since neither fits the buffer
+    let (init, sent) = tokio::join!(conn.initialize::<TestRequest>(TestResponse {}), async {
+        let init = MyRequest::from(TestRequest {}).into_message(1);
+        client.inject(&init).await.unwrap();
+        client.next_sent().await.unwrap()
+    });
+    init.unwrap();
+    assert_eq!(
+        sent,
+        Some(MyResponse::from(TestResponse {}).into_message(1))
+    );
+}
@@ -0,0 +1,198 @@
+use super::*;
+use seraphic::{
+    codec::{Codec, LengthDelimitedCodec, NewlineDelimitedCodec},
+    packet::PacketRead,
+    RequestWrapper,
+};
+
+fn messages() -> Vec<Message> {
+    vec![
+        MyRequest::from(TestRequest {}).into_message(0),
+        MyResponse::from(TestResponse {}).into_message(0),
+        MyRequest::from(FooRequest {}).into_message("foo"),
+    ]
+}
+
+fn roundtrip<C: Codec>() {
+    let mut stream = vec![];
+    for msg in messages() {
+        stream.extend(C::encode(&msg).unwrap());
+    }
+    let mut reader = stream.as_slice();
+    let mut decoded = vec![];
+    while let Some(msg) = C::decode::<Message>(&mut reader).unwrap() {
+        decoded.push(msg);
+    }
+    assert_eq!(decoded, messages());
+}
+
+#[test]
+fn newline_delimited() {
+    roundtrip::<NewlineDelimitedCodec>();
+
+    let encoded = NewlineDelimitedCodec::encode(&messages()[0]).unwrap();
+    assert_eq!(encoded.iter().filter(|b| **b == b'\n').count(), 1);
+    assert_eq!(encoded.last(), Some(&b'\n'));
+
+    // as sent by another implementation, blank lines between messages are skipped
+    let other = "{\"jsonrpc\": \"2.0\", \"method\": \"test_foo\", \"params\": {}, \"id\": 1}\n\n";
+    let mut reader = other.as_bytes();
+    assert_eq!(
+        NewlineDelimitedCodec::decode::<Message>(&mut reader).unwrap(),
+        Some(MyRequest::from(FooRequest {}).into_message(1))
+    );
+    assert!(NewlineDelimitedCodec::decode::<Message>(&mut reader)
+        .unwrap()
+        .is_none());
+
+    let mut reader = "not json\n".as_bytes();
+    assert!(NewlineDelimitedCodec::decode::<Message>(&mut reader).is_err());
+}
+
+#[test]
+fn length_delimited_matches_tcp_packet() {
+    roundtrip::<LengthDelimitedCodec>();
+
+    let msg = &messages()[0];
+    let encoded = LengthDelimitedCodec::encode(msg).unwrap();
+    assert_eq!(encoded, MessagePacket::from(msg).buffer());
+    assert_eq!(
+        MessagePacket::read(&mut encoded.as_slice()).unwrap(),
+        PacketRead::Message(msg.clone())
+    );
+}
+
+#[test]
+fn length_delimited_truncated() {
+    let encoded = LengthDelimitedCodec::encode(&messages()[0]).unwrap();
+    for len in [2, encoded.len() - 1] {
+        let mut reader = &encoded[..len];
+        let err = LengthDelimitedCodec::decode::<Message>(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // the reader is left at the start of the second packet
+    let mut stream = encoded.clone();
+    stream.extend(&encoded[..2]);
+    let mut reader = std::io::BufReader::with_capacity(64, stream.as_slice());
+    assert_eq!(
+        LengthDelimitedCodec::decode::<Message>(&mut reader).unwrap(),
+        Some(messages()[0].clone())
+    );
+    assert!(LengthDelimitedCodec::decode::<Message>(&mut reader).is_err());
+}
+
+#[test]
+fn packet_codec_matches_tcp_packet() {
+    use seraphic::tokio::PacketCodec;
+    use tokio_util::{
+        bytes::BytesMut,
+        codec::{Decoder, Encoder},
+    };
+
+    let mut codec = PacketCodec::<Message>::new();
+    let mut encoded = BytesMut::new();
+    for msg in messages() {
+        codec.encode(msg, &mut encoded).unwrap();
+    }
+    let packets: Vec<u8> = messages()
+        .iter()
+        .flat_map(|msg| MessagePacket::from(msg).buffer().to_vec())
+        .collect();
+    assert_eq!(encoded.as_ref(), packets.as_slice());
+
+    // packets arriving a byte at a time, with one that isn't a message in the middle
+    let mut stream = packets.clone();
+    stream.splice(0..0, [3, 0, 0, 0, b'b', b'a', b'd']);
+    let mut src = BytesMut::new();
+    let mut decoded = vec![];
+    for byte in stream {
+        src.extend_from_slice(&[byte]);
+        if let Some(read) = codec.decode(&mut src).unwrap() {
+            decoded.push(read);
+        }
+    }
+    assert!(src.is_empty());
+    assert!(matches!(
+        &decoded[0],
+        PacketRead::Malformed { payload, .. } if payload == b"bad"
+    ));
+    let messages: Vec<_> = messages().into_iter().map(PacketRead::Message).collect();
+    assert_eq!(decoded[1..], messages);
+}
+
+#[tokio::test]
+async fn framed_against_server() {
+    use super::async_server::EchoHandler;
+    use futures_util::{stream, SinkExt, StreamExt};
+    use seraphic::tokio::{framed, Server};
+
+    let mut server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (stream, accepted) = tokio::join!(tokio::net::TcpStream::connect(addr), server.next());
+    let client_addr = accepted.unwrap().unwrap();
+    let (mut writer, mut reader) = framed::<MyRequest, MyResponse>(stream.unwrap());
+
+    writer
+        .send(MyRequest::from(TestRequest {}).into_message(0))
+        .await
+        .unwrap();
+    assert_eq!(
+        reader.next().await.unwrap().unwrap(),
+        PacketRead::Message(MyResponse::from(TestResponse {}).into_message(0))
+    );
+
+    // far more than the socket buffers hold, so sending has to wait on the server
+    const COUNT: i64 = 10_000;
+    let mut requests =
+        stream::iter((1..=COUNT).map(|id| Ok(MyRequest::from(FooRequest {}).into_message(id))));
+    let send = async {
+        writer.send_all(&mut requests).await.unwrap();
+        writer.close().await.unwrap();
+    };
+    let receive = async {
+        let mut next = 1;
+        while let Some(read) = reader.next().await {
+            assert_eq!(
+                read.unwrap(),
+                PacketRead::Message(MyResponse::from(FooResponse {}).into_message(next))
+            );
+            next += 1;
+        }
+        next - 1
+    };
+    let ((), received) = tokio::join!(send, receive);
+    assert_eq!(received, COUNT);
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn framed_io_duplex() {
+    use futures_util::SinkExt;
+    use seraphic::{packet::PacketBuffer, tokio::framed_io};
+
+    let (client, mut server) = tokio::io::duplex(64);
+    let (mut writer, _reader) = framed_io::<_, MyRequest, MyResponse>(client);
+    let send = async {
+        for msg in messages() {
+            writer.send(msg).await.unwrap();
+        }
+    };
+    let receive = async {
+        let mut buffer = PacketBuffer::new();
+        let mut received = vec![];
+        for _ in messages() {
+            received.push(
+                MessagePacket::async_read_buffered(&mut server, &mut buffer)
+                    .await
+                    .unwrap(),
+            );
+        }
+        received
+    };
+    let ((), received) = tokio::join!(send, receive);
+    let messages: Vec<_> = messages().into_iter().map(PacketRead::Message).collect();
+    assert_eq!(received, messages);
+}
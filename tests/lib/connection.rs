@@ -0,0 +1,479 @@
+use super::{async_server::EchoHandler, *};
+use seraphic::{
+    tokio::{
+        AsyncServerConnection, ClientConnection, Connection, Server, ServerConnectionHandler,
+        ServerHandlerResult,
+    },
+    RequestId, RequestWrapper,
+};
+use std::time::Duration;
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test")]
+pub struct PayloadRequest {
+    n: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PayloadResponse {
+    n: u64,
+}
+
+#[derive(Debug, Clone, RequestWrapper, PartialEq)]
+pub enum PayloadRequests {
+    Payload(PayloadRequest),
+    Foo(FooRequest),
+}
+
+#[derive(Debug, Clone, ResponseWrapper, PartialEq)]
+pub enum PayloadResponses {
+    Payload(PayloadResponse),
+    Foo(FooResponse),
+}
+
+/// Answers every request with what it was sent
+pub struct PayloadHandler;
+
+impl ServerConnectionHandler<PayloadRequests, PayloadResponses> for PayloadHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<PayloadRequests, PayloadResponses>,
+    ) -> ServerHandlerResult {
+        while let Some(msg) = conn.recv().await? {
+            let seraphic::Message::Req { id, req } = msg else {
+                continue;
+            };
+            let res = match req {
+                PayloadRequests::Payload(PayloadRequest { n }) => {
+                    PayloadResponses::from(PayloadResponse { n })
+                }
+                PayloadRequests::Foo(_) => PayloadResponses::from(FooResponse {}),
+            };
+            conn.send(&res.into_message(id)).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn echo_server() -> (
+    Server<MyRequest, MyResponse, EchoHandler>,
+    std::net::SocketAddr,
+) {
+    let server = Server::<MyRequest, MyResponse, EchoHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    (server, addr)
+}
+
+#[tokio::test]
+async fn connection_request_response() {
+    let (mut server, addr) = echo_server().await;
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let mut conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    let init = MyRequest::from(TestRequest {}).into_message(0);
+    conn.sender.send(init).await.unwrap();
+    assert_eq!(
+        conn.receiver.recv().await,
+        Some(MyResponse::from(TestResponse {}).into_message(0))
+    );
+
+    for id in 1..4 {
+        let foo = MyRequest::from(FooRequest {}).into_message(id);
+        conn.sender.send(foo).await.unwrap();
+    }
+    for id in 1..4 {
+        assert_eq!(
+            conn.receiver.recv().await,
+            Some(MyResponse::from(FooResponse {}).into_message(id))
+        );
+    }
+
+    conn.shutdown(Duration::from_secs(5)).await.unwrap();
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn connection_ends_when_server_disconnects() {
+    let (mut server, addr) = echo_server().await;
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let mut conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    assert!(server.disconnect(&client_addr, true));
+    assert_eq!(conn.receiver.recv().await, None);
+    tokio::time::timeout(Duration::from_secs(5), conn.join())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn client_connection_initialize() {
+    let (mut server, addr) = echo_server().await;
+    let (conn, accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    let res = conn.initialize(TestRequest {}).await.unwrap();
+    assert_eq!(res, Ok(TestResponse {}));
+
+    // the channels can be handed to other tasks
+    let (sender, receiver) = (conn.sender(), conn.receiver());
+    let task = tokio::spawn(async move {
+        sender
+            .send(MyRequest::from(FooRequest {}).into_message(1))
+            .await
+            .unwrap();
+        receiver.lock().await.recv().await
+    });
+    assert_eq!(
+        task.await.unwrap(),
+        Some(MyResponse::from(FooResponse {}).into_message(1))
+    );
+
+    conn.shutdown(Duration::from_secs(5)).await.unwrap();
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn client_connection_initialize_errors() {
+    use seraphic::{error::ErrorCode, SeraphicError};
+
+    let (mut server, addr) = echo_server().await;
+    let (conn, accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let conn = conn.unwrap();
+    accepted.unwrap().unwrap();
+
+    // the echo server only initializes with a TestRequest
+    let err = conn.initialize(FooRequest {}).await.unwrap().unwrap_err();
+    assert_eq!(
+        err.code,
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START)
+    );
+
+    // nobody answers on this one
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (conn, _accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect(listener.local_addr().unwrap()),
+        listener.accept()
+    );
+    let conn = conn.unwrap().with_init_timeout(Duration::from_millis(50));
+    let Err(SeraphicError::Io(err)) = conn.initialize(TestRequest {}).await else {
+        panic!("expected a timeout");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn connection_as_stream() {
+    use futures_util::StreamExt;
+
+    let (mut server, addr) = echo_server().await;
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect(addr),
+        server.next()
+    );
+    let conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    let sender = conn.sender.clone();
+    sender
+        .send(MyRequest::from(TestRequest {}).into_message(0))
+        .await
+        .unwrap();
+    for id in 1..4 {
+        sender
+            .send(MyRequest::from(FooRequest {}).into_message(id))
+            .await
+            .unwrap();
+    }
+
+    let mut stream = conn.into_stream();
+    let mut ids = vec![];
+    while let Some(msg) = stream.next().await {
+        let msg = msg.unwrap();
+        ids.push(msg.id().clone());
+        if ids.len() == 4 {
+            assert!(server.disconnect(&client_addr, true));
+        }
+    }
+    assert_eq!(ids, (0..4).map(RequestId::from).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn connection_stream_reports_transport_errors() {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect(listener.local_addr().unwrap()),
+        listener.accept()
+    );
+    // half a header, then gone
+    let (mut server_side, _) = accepted.unwrap();
+    server_side.write_all(&[8, 0]).await.unwrap();
+    drop(server_side);
+
+    let mut stream = conn.unwrap().into_stream();
+    let Some(Err(seraphic::SeraphicError::Io(err))) = stream.next().await else {
+        panic!("expected the read error");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn connection_stream_forwards_into_sink() {
+    use futures_util::StreamExt;
+    use seraphic::{
+        packet::{PacketBuffer, PacketRead, TcpPacket},
+        Message,
+    };
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    // one peer sends a few requests, the other collects what it is sent
+    let source = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let destination = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (from, to, source_side, destination_side) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect(source.local_addr().unwrap()),
+        Connection::<MyRequest, MyResponse>::connect(destination.local_addr().unwrap()),
+        source.accept(),
+        destination.accept()
+    );
+    let (mut source_side, _) = source_side.unwrap();
+    let (mut destination_side, _) = destination_side.unwrap();
+
+    let sent: Vec<_> = (0..3)
+        .map(|id| MyRequest::from(FooRequest {}).into_message(id))
+        .collect();
+    for msg in &sent {
+        let packet = TcpPacket::from(msg);
+        source_side.write_all(packet.buffer()).await.unwrap();
+    }
+    drop(source_side);
+
+    let (sink, _) = to.unwrap().into_split();
+    from.unwrap().into_stream().forward(sink).await.unwrap();
+
+    // closing the sink shut down the writing half once everything was written
+    let mut buffer = PacketBuffer::new();
+    let mut received = vec![];
+    loop {
+        match TcpPacket::<Message<MyRequest, MyResponse>>::async_read_buffered(
+            &mut destination_side,
+            &mut buffer,
+        )
+        .await
+        .unwrap()
+        {
+            PacketRead::Message(msg) => received.push(msg),
+            PacketRead::Disconnected => break,
+            _ => panic!("expected whole messages"),
+        }
+    }
+    assert_eq!(received, sent);
+}
+
+#[tokio::test]
+async fn connection_concurrent_requests() {
+    let mut server =
+        Server::<PayloadRequests, PayloadResponses, PayloadHandler>::listen("127.0.0.1:0")
+            .await
+            .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (conn, accepted) = tokio::join!(
+        Connection::<PayloadRequests, PayloadResponses>::connect(addr),
+        server.next()
+    );
+    let conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    // answers to messages sent through `sender` still go to `receiver`
+    let unrelated = PayloadRequests::from(FooRequest {}).into_message("unrelated");
+    conn.sender.send(unrelated).await.unwrap();
+
+    let conn = std::sync::Arc::new(conn);
+    let requests: Vec<_> = (0..200)
+        .map(|n| {
+            let conn = std::sync::Arc::clone(&conn);
+            tokio::spawn(async move { conn.request(PayloadRequest { n }).await })
+        })
+        .collect();
+    for (n, request) in requests.into_iter().enumerate() {
+        let res = request.await.unwrap().unwrap();
+        assert_eq!(res, Ok(PayloadResponse { n: n as u64 }));
+    }
+
+    let mut conn = std::sync::Arc::into_inner(conn).unwrap();
+    assert_eq!(
+        conn.receiver.recv().await,
+        Some(PayloadResponses::from(FooResponse {}).into_message("unrelated"))
+    );
+
+    conn.shutdown(Duration::from_secs(5)).await.unwrap();
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn connection_request_timeout() {
+    use seraphic::SeraphicError;
+
+    // nobody answers on this one
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (conn, accepted) = tokio::join!(
+        Connection::<PayloadRequests, PayloadResponses>::connect(listener.local_addr().unwrap()),
+        listener.accept()
+    );
+    let conn = conn.unwrap();
+    let Err(SeraphicError::Io(err)) = conn
+        .request_timeout(PayloadRequest { n: 1 }, Duration::from_millis(50))
+        .await
+    else {
+        panic!("expected a timeout");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    // once the server is gone, nothing can be answered
+    drop(accepted.unwrap());
+    let Err(SeraphicError::Disconnected) = conn.request(PayloadRequest { n: 2 }).await else {
+        panic!("expected the request to be dropped");
+    };
+}
+
+#[tokio::test]
+async fn connection_request_with_full_receiver() {
+    use seraphic::{
+        packet::{PacketRead, TcpPacket},
+        tokio::connection::CHANNEL_CAPACITY,
+        Message,
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (conn, accepted) = tokio::join!(
+        Connection::<PayloadRequests, PayloadResponses>::connect(listener.local_addr().unwrap()),
+        listener.accept()
+    );
+    let mut conn = std::sync::Arc::new(conn.unwrap());
+    let (mut server_side, _) = accepted.unwrap();
+
+    let request = tokio::spawn({
+        let conn = std::sync::Arc::clone(&conn);
+        async move { conn.request(PayloadRequest { n: 7 }).await }
+    });
+    let PacketRead::Message(Message::Req { id, .. }) =
+        TcpPacket::<Message<PayloadRequests, PayloadResponses>>::async_read(&mut server_side)
+            .await
+            .unwrap()
+    else {
+        panic!("expected the request");
+    };
+
+    // more unsolicited messages than `receiver` holds, which nobody reads, and then the answer
+    let unsolicited: Vec<_> = (0..CHANNEL_CAPACITY * 2)
+        .map(|n| PayloadResponses::from(FooResponse {}).into_message(format!("unsolicited {n}")))
+        .collect();
+    for msg in &unsolicited {
+        TcpPacket::async_write(&mut server_side, msg).await.unwrap();
+    }
+    let answer: Message<PayloadRequests, _> =
+        PayloadResponses::from(PayloadResponse { n: 7 }).into_message(id);
+    TcpPacket::async_write(&mut server_side, &answer)
+        .await
+        .unwrap();
+
+    let res = tokio::time::timeout(Duration::from_secs(5), request)
+        .await
+        .expect("the answer was held up behind the full receiver")
+        .unwrap()
+        .unwrap();
+    assert_eq!(res, Ok(PayloadResponse { n: 7 }));
+
+    // nothing unsolicited was lost, and it all comes in order
+    let conn = std::sync::Arc::get_mut(&mut conn).unwrap();
+    for msg in unsolicited {
+        assert_eq!(conn.receiver.recv().await, Some(msg));
+    }
+}
+
+#[tokio::test]
+async fn connection_read_timeouts() {
+    use seraphic::tokio::ReadTimeouts;
+    use tokio::io::AsyncWriteExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let timeouts = ReadTimeouts {
+        idle: Some(Duration::from_millis(100)),
+        frame: Some(Duration::from_millis(100)),
+    };
+
+    // half a header, then the server goes quiet without hanging up
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect_with(addr, timeouts),
+        listener.accept()
+    );
+    let (mut server_side, _) = accepted.unwrap();
+    server_side.write_all(&[8, 0]).await.unwrap();
+    let err = tokio::time::timeout(Duration::from_secs(5), conn.unwrap().join())
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    let (conn, accepted) = tokio::join!(
+        Connection::<MyRequest, MyResponse>::connect_with(addr, timeouts),
+        listener.accept()
+    );
+    let _server_side = accepted.unwrap();
+    let err = tokio::time::timeout(Duration::from_secs(5), conn.unwrap().join())
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    drop(server_side);
+}
+
+#[tokio::test]
+async fn client_connection_initialize_until_cancelled() {
+    use seraphic::SeraphicError;
+    use tokio_util::sync::CancellationToken;
+
+    // nobody answers
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (conn, _accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect(listener.local_addr().unwrap()),
+        listener.accept()
+    );
+    let conn = conn.unwrap();
+
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        }
+    });
+    let Err(SeraphicError::Aborted) = conn
+        .initialize_until(TestRequest {}, cancel.cancelled())
+        .await
+    else {
+        panic!("expected the handshake to be aborted");
+    };
+    conn.shutdown(Duration::from_secs(5)).await.unwrap();
+}
@@ -0,0 +1,483 @@
+use seraphic::{
+    error::{Error, ErrorCode},
+    Response, SeraphicError,
+};
+use serde_json::json;
+
+#[test]
+fn error_code_is_numeric() {
+    for (code, n) in [
+        (ErrorCode::ParseError, -32700),
+        (ErrorCode::InvalidRequest, -32600),
+        (ErrorCode::MethodNotFound, -32601),
+        (ErrorCode::InvalidParams, -32602),
+        (ErrorCode::InternalError, -32603),
+    ] {
+        assert_eq!(serde_json::to_value(code).unwrap(), json!(n));
+        assert_eq!(serde_json::from_value::<ErrorCode>(json!(n)).unwrap(), code);
+    }
+    assert!(serde_json::from_value::<ErrorCode>(json!("ParseError")).is_err());
+}
+
+#[test]
+fn error_codes_roundtrip_exactly() {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let random = std::iter::repeat_with(move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as i64
+    });
+    let edges = [
+        i64::MIN,
+        i64::MAX,
+        0,
+        -32768,
+        -32700,
+        -32603,
+        -32100,
+        ErrorCode::SERVER_ERROR_START,
+        -32050,
+        ErrorCode::SERVER_ERROR_END,
+        -31999,
+        -29900,
+        -29901,
+    ];
+    let in_server_range = ErrorCode::SERVER_ERROR_START..=ErrorCode::SERVER_ERROR_END;
+
+    for n in edges
+        .into_iter()
+        .chain(in_server_range)
+        .chain(random.take(10_000))
+    {
+        let code: ErrorCode = serde_json::from_value(json!(n)).unwrap();
+        assert_eq!(code.code(), n);
+        let json = serde_json::to_value(code).unwrap();
+        assert_eq!(json, json!(n));
+        assert_eq!(serde_json::from_value::<ErrorCode>(json).unwrap(), code);
+    }
+
+    assert!(matches!(
+        ErrorCode::from_code(-32050),
+        ErrorCode::ServerError(-32050)
+    ));
+    assert!(matches!(
+        ErrorCode::from_code(-32100),
+        ErrorCode::Custom(-32100)
+    ));
+}
+
+#[test]
+fn error_code_classification() {
+    for code in [
+        ErrorCode::ParseError,
+        ErrorCode::InvalidRequest,
+        ErrorCode::MethodNotFound,
+        ErrorCode::InvalidParams,
+        ErrorCode::Custom(-32602),
+    ] {
+        assert!(code.is_protocol_error(), "{code:?}");
+        assert!(!code.is_server_error() && !code.is_transport() && !code.is_retryable());
+    }
+
+    for code in [
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START),
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_END),
+        ErrorCode::Custom(-32050),
+    ] {
+        assert!(code.is_server_error(), "{code:?}");
+        assert!(!code.is_protocol_error() && !code.is_retryable());
+    }
+
+    for code in [
+        ErrorCode::Disconnect,
+        ErrorCode::Timeout,
+        ErrorCode::Custom(-29901),
+    ] {
+        assert!(code.is_transport() && code.is_retryable(), "{code:?}");
+        assert!(!code.is_protocol_error() && !code.is_server_error());
+    }
+
+    for code in [
+        ErrorCode::InternalError,
+        ErrorCode::Aborted,
+        ErrorCode::Custom(1001),
+    ] {
+        assert!(!code.is_protocol_error() && !code.is_server_error() && !code.is_retryable());
+    }
+}
+
+#[test]
+fn custom_error_codes() {
+    let err = Error::app(1001, "quota exceeded");
+    assert_eq!(err.code, ErrorCode::Custom(1001));
+    assert_eq!(err.code.code(), 1001);
+
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json, json!({"code": 1001, "message": "quota exceeded"}));
+    assert_eq!(serde_json::from_value::<Error>(json).unwrap(), err);
+
+    assert_eq!(ErrorCode::from_code(-32601), ErrorCode::MethodNotFound);
+    assert!(matches!(
+        ErrorCode::from_code(-32601),
+        ErrorCode::MethodNotFound
+    ));
+    assert_eq!(ErrorCode::Custom(-32601), ErrorCode::MethodNotFound);
+    assert_ne!(ErrorCode::Custom(1), ErrorCode::Custom(2));
+    assert_eq!(
+        serde_json::from_value::<ErrorCode>(json!(-32050)).unwrap(),
+        ErrorCode::Custom(-32050)
+    );
+}
+
+#[test]
+fn error_from_other_implementations() {
+    let err: Error =
+        serde_json::from_str(r#"{"code": -32601, "message": "Method not found"}"#).unwrap();
+    assert_eq!(
+        err,
+        Error {
+            code: ErrorCode::MethodNotFound,
+            message: "Method not found".to_string(),
+            data: None,
+        }
+    );
+
+    let err: Error =
+        serde_json::from_str(r#"{"code": -32602, "message": "Invalid params", "data": [1]}"#)
+            .unwrap();
+    assert_eq!(err.code, ErrorCode::InvalidParams);
+    assert_eq!(err.data, Some(json!([1])));
+}
+
+#[test]
+fn error_response_matches_spec() {
+    // from the examples in the JSON-RPC 2.0 specification
+    let res = Response::from_error(
+        "1",
+        Error {
+            code: ErrorCode::MethodNotFound,
+            message: "Method not found".to_string(),
+            data: None,
+        },
+    );
+    let expected = r#"{"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": "1"}"#;
+    assert_eq!(
+        serde_json::to_value(&res).unwrap(),
+        serde_json::from_str::<serde_json::Value>(expected).unwrap()
+    );
+    assert_eq!(serde_json::from_str::<Response>(expected).unwrap(), res);
+
+    let res = Response::from_error(
+        seraphic::RequestId::Null,
+        Error {
+            code: ErrorCode::ParseError,
+            message: "Parse error".to_string(),
+            data: None,
+        },
+    );
+    assert_eq!(
+        serde_json::to_string(&res).unwrap(),
+        r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"},"id":null}"#
+    );
+}
+
+fn not_found(method: &str) -> seraphic::error::ErrorKind {
+    seraphic::error::ErrorKind::other(
+        format!("no method named {method}"),
+        ErrorCode::MethodNotFound,
+    )
+}
+
+#[test]
+fn error_kind_owns_message() {
+    let kind = not_found("foo");
+    let err: Error = kind.clone().into();
+    assert_eq!(err, Error::from(kind));
+    assert_eq!(err.code, ErrorCode::MethodNotFound);
+    assert_eq!(err.message, "no method named foo");
+    assert_eq!(err.data, None);
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+struct Quota {
+    used: u32,
+    limit: u32,
+}
+
+#[test]
+fn typed_error_data() {
+    let quota = Quota {
+        used: 11,
+        limit: 10,
+    };
+    let err = Error::app(1001, "quota exceeded")
+        .with_data(&quota)
+        .unwrap();
+    assert_eq!(err.data_as::<Quota>().unwrap(), Some(quota.clone()));
+
+    let res = Response::from_error(7, err);
+    let json = serde_json::to_string(&res).unwrap();
+    let res: Response = serde_json::from_str(&json).unwrap();
+    let err = res.error.unwrap();
+    assert_eq!(err.code, ErrorCode::Custom(1001));
+    assert_eq!(err.data_as::<Quota>().unwrap(), Some(quota));
+    assert!(err.data_as::<String>().is_err());
+
+    let err = Error::new(ErrorCode::InternalError, "oops");
+    assert_eq!(err.data_as::<Quota>().unwrap(), None);
+}
+
+#[test]
+fn standard_errors() {
+    for (err, code) in [
+        (
+            Error::invalid_params("a is missing"),
+            ErrorCode::InvalidParams,
+        ),
+        (Error::parse_error("eof"), ErrorCode::ParseError),
+        (
+            Error::invalid_request("no method"),
+            ErrorCode::InvalidRequest,
+        ),
+        (Error::internal("oops"), ErrorCode::InternalError),
+    ] {
+        assert_eq!(err.code, code);
+        assert_eq!(err.data, None);
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["code"], json!(code.code()));
+        assert_eq!(serde_json::from_value::<Error>(json).unwrap(), err);
+    }
+
+    let err = Error::method_not_found("test_bar");
+    assert_eq!(err.code, ErrorCode::MethodNotFound);
+    assert_eq!(err.message, "method not found: test_bar");
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["data"], json!({"method": "test_bar"}));
+    assert_eq!(serde_json::from_value::<Error>(json).unwrap(), err);
+}
+
+#[test]
+fn wrong_method_is_method_not_found() {
+    use super::FooRequest;
+    use seraphic::RpcRequest;
+
+    for method in ["test_bar", "other_foo", "foo"] {
+        let req = seraphic::Request {
+            jsonrpc: seraphic::JSONRPC_FIELD.to_string(),
+            method: method.to_string(),
+            params: json!({}),
+            id: 0.into(),
+        };
+        match FooRequest::try_from_request(&req).unwrap_err() {
+            SeraphicError::Rpc(err) => assert_eq!(err, Error::method_not_found(method)),
+            other => panic!("expected an rpc error, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn io_errors_map_to_codes() {
+    use std::io::{Error as IoError, ErrorKind};
+
+    for (kind, code) in [
+        (ErrorKind::ConnectionReset, ErrorCode::Disconnect),
+        (ErrorKind::ConnectionAborted, ErrorCode::Disconnect),
+        (ErrorKind::BrokenPipe, ErrorCode::Disconnect),
+        (ErrorKind::UnexpectedEof, ErrorCode::Disconnect),
+        (ErrorKind::TimedOut, ErrorCode::Timeout),
+        (ErrorKind::WouldBlock, ErrorCode::Timeout),
+        (ErrorKind::InvalidData, ErrorCode::InternalError),
+        (ErrorKind::Other, ErrorCode::InternalError),
+    ] {
+        let err = Error::from(IoError::new(kind, "boom"));
+        assert_eq!(err.code, code, "{kind:?}");
+        assert_eq!(err.message, "boom");
+        assert_eq!(err.data, Some(json!({ "kind": format!("{kind:?}") })));
+    }
+}
+
+#[test]
+fn packet_read_keeps_io_error_kind() {
+    use seraphic::packet::{PacketRead, TcpPacket};
+
+    // a header promising more bytes than arrive
+    let mut truncated: &[u8] = &[10, 0, 0, 0, b'{'];
+    let err = TcpPacket::<serde_json::Value>::read(&mut truncated).unwrap_err();
+    assert_eq!(Error::from(err).code, ErrorCode::Disconnect);
+
+    // a whole packet that isn't JSON is not an io error, the stream can still be read
+    let mut garbage: &[u8] = &[3, 0, 0, 0, b'n', b'o', b'!'];
+    assert!(matches!(
+        TcpPacket::<serde_json::Value>::read(&mut garbage).unwrap(),
+        PacketRead::Malformed { payload, .. } if payload == b"no!"
+    ));
+}
+
+#[test]
+fn error_context_chain() {
+    use seraphic::error::{ErrorContext, ErrorKind};
+
+    let res: Result<(), Error> = Err(ErrorKind::Disconnect.into());
+    let err = res
+        .context("waiting for response")
+        .context("client initialize")
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::Disconnect);
+    assert_eq!(
+        err.message,
+        "client initialize -> waiting for response -> disconnected channel"
+    );
+    assert_eq!(
+        err.data,
+        Some(json!({
+            "trace": [
+                "disconnected channel",
+                "waiting for response -> disconnected channel"
+            ]
+        }))
+    );
+
+    // existing data is kept alongside the trace
+    let err = Error::method_not_found("foo").context("dispatch");
+    assert_eq!(
+        err.data,
+        Some(json!({ "method": "foo", "trace": ["method not found: foo"] }))
+    );
+    let err = Error::internal("oops")
+        .with_data([1, 2])
+        .unwrap()
+        .context("handler");
+    assert_eq!(
+        err.data,
+        Some(json!({ "data": [1, 2], "trace": ["internal error: oops"] }))
+    );
+
+    let res: Result<(), std::io::Error> = Err(std::io::ErrorKind::BrokenPipe.into());
+    let err = res.context("sending response").unwrap_err();
+    assert_eq!(err.code, ErrorCode::Disconnect);
+    assert!(err.message.starts_with("sending response -> "));
+}
+
+#[test]
+fn uninitialized_payload_is_capped() {
+    use super::{FooRequest, Message, MyRequest};
+    use seraphic::error::{ErrorKind, DEFAULT_MAX_ERROR_PAYLOAD};
+    use seraphic::RequestWrapper;
+
+    let small: Message = MyRequest::from(FooRequest {}).into_message(0);
+    let err = Error::from(ErrorKind::uninitialized(&small));
+    assert_eq!(err.data, Some(serde_json::to_value(&small).unwrap()));
+
+    let huge: Message = Message::Err {
+        id: 0.into(),
+        err: Error::internal("big")
+            .with_data("a".repeat(5_000_000))
+            .unwrap(),
+    };
+    let err = Error::from(ErrorKind::uninitialized(&huge));
+    let data = err.data.as_ref().unwrap();
+    assert_eq!(data["truncated"], true);
+    assert!(data["size"].as_u64().unwrap() > 5_000_000);
+    assert_eq!(
+        data["payload"].as_str().unwrap().len(),
+        DEFAULT_MAX_ERROR_PAYLOAD
+    );
+    let res = Response::from_error(0, err);
+    assert!(serde_json::to_vec(&res).unwrap().len() < 2 * DEFAULT_MAX_ERROR_PAYLOAD);
+
+    let err = Error::from(ErrorKind::uninitialized_with_limit(&small, 8));
+    assert_eq!(err.data.unwrap()["payload"].as_str().unwrap().len(), 8);
+}
+
+#[test]
+fn seraphic_error_variants() {
+    use super::params::SumResponse;
+    use seraphic::{ResponseWrapper, RpcResponse};
+
+    let res = Response {
+        jsonrpc: seraphic::JSONRPC_FIELD.to_string(),
+        result: Some(json!({"sum": "two"})),
+        error: None,
+        id: 0.into(),
+    };
+    assert!(matches!(
+        SumResponse::try_from_response(&res),
+        Err(SeraphicError::Json(_))
+    ));
+
+    let res = seraphic::msg::IdentifiedResponse {
+        id: "product".to_string(),
+        res,
+    };
+    assert!(matches!(
+        super::MyResponse::try_from_res(res),
+        Err(SeraphicError::Protocol(_))
+    ));
+
+    let packet = seraphic::packet::TcpPacket::<super::Message>::from(
+        &seraphic::RequestWrapper::into_message(super::MyRequest::Foo(super::FooRequest {}), 0),
+    );
+    let mut bytes = packet.buffer().to_vec();
+    bytes.truncate(bytes.len() - 1);
+    let truncated: seraphic::packet::TcpPacket<super::Message> =
+        serde_json::from_value(json!(bytes)).unwrap();
+    assert!(matches!(
+        truncated.try_into_inner(),
+        Err(SeraphicError::Json(_))
+    ));
+
+    let err = SeraphicError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+    assert!(
+        matches!(err, SeraphicError::Io(ref io) if io.kind() == std::io::ErrorKind::BrokenPipe)
+    );
+
+    let err: seraphic::MainErr = SeraphicError::Disconnected.into();
+    assert_eq!(err.to_string(), "disconnected");
+    assert!(matches!(
+        *err.downcast::<SeraphicError>().unwrap(),
+        SeraphicError::Disconnected
+    ));
+}
+
+#[test]
+fn retry_hint_survives_the_wire() {
+    use seraphic::error::RetryHint;
+    use std::time::Duration;
+
+    let err = Error::app(429, "rate limited")
+        .with_data(json!({"bucket": "writes"}))
+        .unwrap()
+        .retryable_after(Duration::from_millis(1500));
+    let wire = serde_json::to_string(&Response::from_error(1, err)).unwrap();
+
+    let res: Response = serde_json::from_str(&wire).unwrap();
+    let err = res.error.unwrap();
+    assert_eq!(
+        err.retry_hint(),
+        Some(RetryHint {
+            retryable: true,
+            retry_after: Some(Duration::from_millis(1500)),
+        })
+    );
+    assert_eq!(err.data.unwrap()["bucket"], "writes");
+
+    let err = Error::invalid_params("a is missing").permanent();
+    assert_eq!(
+        err.retry_hint(),
+        Some(RetryHint {
+            retryable: false,
+            retry_after: None,
+        })
+    );
+
+    assert_eq!(Error::app(1, "no data").retry_hint(), None);
+    for data in [
+        json!("text"),
+        json!({"seraphic": "foreign"}),
+        json!({"seraphic": {"retryable": "yes"}}),
+    ] {
+        let err = Error::app(1, "foreign data").with_data(data).unwrap();
+        assert_eq!(err.retry_hint(), None);
+    }
+}
@@ -0,0 +1,278 @@
+use super::*;
+use seraphic::{
+    error::ErrorCode,
+    http::{HttpClientConnection, HttpServerConnection},
+    RequestId, RequestWrapper,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+async fn serve_one(listener: TcpListener) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut conn = HttpServerConnection::<MyRequest, MyResponse>::new(stream).unwrap();
+    while let Some(msg) = conn.recv().await {
+        // requests with a null id are treated as notifications and left unanswered
+        if let Message::Req {
+            id: id @ (RequestId::Num(_) | RequestId::Str(_)),
+            req: MyRequest::Foo(_),
+        } = msg
+        {
+            conn.send(&MyResponse::from(FooResponse {}).into_message(id))
+                .unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn http_transport() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_one(listener));
+
+    let mut client = HttpClientConnection::<MyRequest, MyResponse>::connect(addr)
+        .await
+        .unwrap();
+    for id in 0..3 {
+        let res = client
+            .send(&MyRequest::from(FooRequest {}).into_message(id))
+            .await
+            .unwrap();
+        assert_eq!(res, Some(MyResponse::from(FooResponse {}).into_message(id)));
+    }
+
+    let notification = MyRequest::from(FooRequest {}).into_message(RequestId::Null);
+    assert_eq!(client.send(&notification).await.unwrap(), None);
+
+    drop(client);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn http_initialize() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = HttpServerConnection::<MyRequest, MyResponse>::new(stream).unwrap();
+        conn.initialize::<TestRequest>(TestResponse {})
+            .await
+            .unwrap();
+        while let Some(msg) = conn.recv().await {
+            let res = MyResponse::from(FooResponse {}).into_message(msg.id().clone());
+            conn.send(&res).unwrap();
+        }
+    });
+
+    let mut client = HttpClientConnection::<MyRequest, MyResponse>::connect(addr)
+        .await
+        .unwrap();
+    // the server only initializes with a TestRequest
+    let err = client.initialize(FooRequest {}).await.unwrap().unwrap_err();
+    assert_eq!(
+        err.code,
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START)
+    );
+    assert_eq!(
+        client.initialize(TestRequest {}).await.unwrap(),
+        Ok(TestResponse {})
+    );
+
+    let res = client
+        .send(&MyRequest::from(FooRequest {}).into_message(1))
+        .await
+        .unwrap();
+    assert_eq!(res, Some(MyResponse::from(FooResponse {}).into_message(1)));
+
+    drop(client);
+    server.await.unwrap();
+}
+
+/// Reads one whole HTTP response: its headers, and then `Content-Length` bytes of body
+async fn read_response(stream: &mut (impl AsyncReadExt + Unpin)) -> String {
+    let mut res = vec![];
+    let mut chunk = [0; 1024];
+    let body_start = loop {
+        if let Some(end) = res.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed in the middle of the headers");
+        res.extend_from_slice(&chunk[..n]);
+    };
+    let headers = String::from_utf8_lossy(&res[..body_start]).to_lowercase();
+    let content_length: usize = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .map_or(0, |len| len.trim().parse().unwrap());
+    while res.len() < body_start + content_length {
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed in the middle of the body");
+        res.extend_from_slice(&chunk[..n]);
+    }
+    String::from_utf8_lossy(&res).to_string()
+}
+
+async fn exchange(stream: &mut TcpStream, req: &str) -> String {
+    stream.write_all(req.as_bytes()).await.unwrap();
+    read_response(stream).await
+}
+
+#[tokio::test]
+async fn http_transport_rejects_bad_requests() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_one(listener));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let res = exchange(&mut stream, "GET /rpc HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 405"), "{res}");
+    let res = exchange(
+        &mut stream,
+        "POST /other HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 404"), "{res}");
+
+    let res = exchange(
+        &mut stream,
+        "POST /rpc HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\n{oops",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+    let body = res.split("\r\n\r\n").nth(1).unwrap();
+    let res: seraphic::Response = serde_json::from_str(body).unwrap();
+    assert_eq!(res.id, RequestId::Null);
+    assert_eq!(res.unwrap_error().code, ErrorCode::ParseError);
+
+    drop(stream);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn http_transport_rejects_large_bodies() {
+    use seraphic::http::MAX_MESSAGE_SIZE;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_one(listener));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let len = MAX_MESSAGE_SIZE + 1;
+    let head = format!("POST /rpc HTTP/1.1\r\nHost: localhost\r\nContent-Length: {len}\r\n\r\n");
+    let (mut read, mut write) = stream.split();
+    let (_, res) = tokio::join!(
+        async {
+            // the server may hang up before taking the last bytes
+            let _ = write.write_all(head.as_bytes()).await;
+            let _ = write.write_all(&vec![b' '; len]).await;
+        },
+        read_response(&mut read)
+    );
+    assert!(res.starts_with("HTTP/1.1 413"), "{res}");
+
+    drop(stream);
+    server.await.unwrap();
+}
+
+/// Posts `body` to a `seraphic::http::serve` server, returning the status and response body
+#[cfg(feature = "tower")]
+async fn post(stream: &mut TcpStream, body: &str) -> (u16, String) {
+    let req = format!(
+        "POST /rpc HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    let res = exchange(stream, &req).await;
+    let status = res[9..12].parse().unwrap();
+    let (_, body) = res.split_once("\r\n\r\n").unwrap();
+    (status, body.to_string())
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn http_serve_json_rpc() {
+    use seraphic::{
+        error::Error, http::serve, tower::SeraphicService, Request, Response, RpcRequest,
+    };
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    let service = SeraphicService::new(move |req: Request| {
+        counted.fetch_add(1, Ordering::Relaxed);
+        async move {
+            if FooRequest::try_from_request(&req).is_ok() {
+                return Ok(Response::from_res(req.id, FooResponse {}));
+            }
+            Err(Error::method_not_found(&req.method))
+        }
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve(listener, service));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let foo = FooRequest::namespace_method();
+
+    let (status, body) = post(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": foo, "params": {}, "id": 1}).to_string(),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let res: Response = serde_json::from_str(&body).unwrap();
+    assert_eq!(res.id, RequestId::from(1));
+    assert_eq!(res.unwrap_result(), json!({}));
+
+    let (status, body) = post(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": "nope", "id": "a"}).to_string(),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let res: Response = serde_json::from_str(&body).unwrap();
+    assert_eq!(res.unwrap_error().code, ErrorCode::MethodNotFound);
+
+    // notifications are run, but not answered
+    let (status, body) = post(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": foo, "params": {}}).to_string(),
+    )
+    .await;
+    assert_eq!((status, body.as_str()), (204, ""));
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    let batch = json!([
+        {"jsonrpc": "2.0", "method": foo, "params": {}, "id": 1},
+        {"jsonrpc": "2.0", "method": foo, "params": {}},
+        {"jsonrpc": "2.0", "method": "nope", "id": 2},
+        {"not": "a request"},
+    ]);
+    let (status, body) = post(&mut stream, &batch.to_string()).await;
+    assert_eq!(status, 200);
+    let answers: Vec<Response> = serde_json::from_str(&body).unwrap();
+    let ids: Vec<_> = answers.iter().map(|res| res.id.clone()).collect();
+    assert_eq!(
+        ids,
+        [RequestId::from(1), RequestId::from(2), RequestId::Null]
+    );
+    assert_eq!(
+        answers[2].clone().unwrap_error().code,
+        ErrorCode::InvalidRequest
+    );
+
+    let batch = json!([{"jsonrpc": "2.0", "method": foo, "params": {}}]);
+    let (status, _) = post(&mut stream, &batch.to_string()).await;
+    assert_eq!(status, 204);
+
+    let (status, body) = post(&mut stream, "{oops").await;
+    assert_eq!(status, 400);
+    let res: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(res["error"]["code"], json!(-32700));
+
+    server.abort();
+}
@@ -1,10 +1,23 @@
 pub mod async_io;
+pub mod async_server;
+pub mod codec;
+pub mod connection;
+pub mod errors;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod params;
+#[cfg(feature = "quic")]
+pub mod quic;
 pub mod serde_;
+#[cfg(feature = "tower")]
+pub mod service;
 pub mod sync_io;
+#[cfg(feature = "ws")]
+pub mod ws;
 use seraphic::{
-    derive::{RequestWrapper, ResponseWrapper, RpcNamespace, RpcRequest},
+    derive::{RequestWrapper, ResponseWrapper, RpcNamespace, RpcRequest, RpcResponse},
     packet::TcpPacket,
-    ResponseWrapper, RpcNamespace, RpcRequest, RpcResponse,
+    ResponseWrapper, RpcRequest, RpcResponse,
 };
 use serde::{Deserialize, Serialize};
 
@@ -13,14 +26,14 @@ pub enum TestNS {
     Test,
 }
 
-#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
 #[rpc_request(namespace = "TestNS:test")]
 pub struct TestRequest {}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TestResponse {}
 
-#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
 #[rpc_request(namespace = "TestNS:test")]
 pub struct FooRequest {}
 
@@ -0,0 +1,461 @@
+use super::*;
+use seraphic::{Request, JSONRPC_FIELD};
+use serde_json::json;
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test")]
+pub struct AddRequest {
+    a: i32,
+    b: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AddResponse {
+    sum: i32,
+}
+
+#[derive(RpcResponse, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[rpc_response(identity = "sum")]
+pub struct SumResponse {
+    sum: i32,
+}
+
+#[derive(RpcResponse, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProductResponse {
+    product: i32,
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test", response = "AddResponse", strict_params)]
+pub struct StrictAddRequest {
+    a: i32,
+    b: i32,
+}
+
+fn request_with_params(params: serde_json::Value) -> Request {
+    Request {
+        jsonrpc: JSONRPC_FIELD.to_string(),
+        method: AddRequest::namespace_method(),
+        params,
+        id: 0.into(),
+    }
+}
+
+#[test]
+fn named_params() {
+    let req = request_with_params(json!({"a": 1, "b": 2}));
+    let add = AddRequest::try_from_request(&req).unwrap();
+    assert_eq!(add, AddRequest { a: 1, b: 2 });
+}
+
+#[test]
+fn positional_params() {
+    let req = request_with_params(json!([1, 2]));
+    let add = AddRequest::try_from_request(&req).unwrap();
+    assert_eq!(add, AddRequest { a: 1, b: 2 });
+
+    let req = request_with_params(json!([1]));
+    assert!(AddRequest::try_from_request(&req).is_err());
+
+    let empty = TestRequest::try_from_json(&json!([])).unwrap();
+    assert_eq!(empty, TestRequest {});
+}
+
+#[test]
+fn extra_params_are_ignored_unless_strict() {
+    const { assert!(!AddRequest::STRICT_PARAMS) };
+    const { assert!(StrictAddRequest::STRICT_PARAMS) };
+
+    let params = json!({"a": 1, "b": 2, "c": 3});
+    let add = AddRequest::try_from_json(&params).unwrap();
+    assert_eq!(add, AddRequest { a: 1, b: 2 });
+    assert!(StrictAddRequest::try_from_json(&params).is_err());
+
+    let params = json!([1, 2, 3]);
+    assert!(AddRequest::try_from_json(&params).is_ok());
+    assert!(StrictAddRequest::try_from_json(&params).is_err());
+
+    let strict = StrictAddRequest::try_from_json(&json!({"a": 1, "b": 2})).unwrap();
+    assert_eq!(strict, StrictAddRequest { a: 1, b: 2 });
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test")]
+pub enum ComputeRequest {
+    Negate(i32),
+    Add { a: i32, b: i32 },
+    Zero,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ComputeResponse {
+    result: i32,
+}
+
+#[test]
+fn enum_params() {
+    for compute in [
+        ComputeRequest::Negate(1),
+        ComputeRequest::Add { a: 1, b: 2 },
+        ComputeRequest::Zero,
+    ] {
+        let req = compute.into_request(0).unwrap();
+        assert_eq!(req.method, "test_compute");
+        assert_eq!(ComputeRequest::try_from_request(&req).unwrap(), compute);
+    }
+
+    let req = request_with_params(json!({"Add": {"a": 1, "b": 2}}));
+    assert!(ComputeRequest::try_from_json(&req.params).is_ok());
+    assert!(ComputeRequest::try_from_json(&json!({"Subtract": 1})).is_err());
+}
+
+fn invalid_params(err: seraphic::SeraphicError) -> Vec<String> {
+    let seraphic::SeraphicError::Rpc(err) = err else {
+        panic!("params errors are rpc errors, got {err:?}");
+    };
+    assert_eq!(err.code, seraphic::error::ErrorCode::InvalidParams);
+    serde_json::from_value(err.data.unwrap()).unwrap()
+}
+
+#[test]
+fn params_error_names_field_and_type() {
+    let req = request_with_params(json!({"a": "one", "b": 2}));
+    let errors = invalid_params(AddRequest::try_from_request(&req).unwrap_err());
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].starts_with("field 'a' failed to deserialize as i32: "),
+        "{errors:?}"
+    );
+    assert!(errors[0].contains("\"one\""), "{errors:?}");
+
+    let req = request_with_params(json!([1, true]));
+    let errors = invalid_params(AddRequest::try_from_request(&req).unwrap_err());
+    assert!(
+        errors[0].starts_with("field 'b' failed to deserialize as i32: "),
+        "{errors:?}"
+    );
+}
+
+#[test]
+fn params_errors_are_reported_together() {
+    let errors = invalid_params(AddRequest::try_from_json(&json!({})).unwrap_err());
+    assert_eq!(
+        errors,
+        ["field 'a' does not exist", "field 'b' does not exist"]
+    );
+
+    let errors =
+        invalid_params(StrictAddRequest::try_from_json(&json!({"b": "two", "c": 3})).unwrap_err());
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    assert_eq!(errors[0], "unknown field 'c' in params");
+    assert_eq!(errors[1], "field 'a' does not exist");
+    assert!(errors[2].starts_with("field 'b' failed to deserialize"));
+
+    let errors = invalid_params(AddRequest::try_from_json(&json!([])).unwrap_err());
+    assert_eq!(
+        errors,
+        [
+            "positional param 0 ('a') does not exist",
+            "positional param 1 ('b') does not exist"
+        ]
+    );
+}
+
+#[test]
+fn derived_response_identity() {
+    assert_eq!(ProductResponse::IDENTITY, "product");
+    assert_eq!(SumResponse::IDENTITY, "sum");
+    // implied by `AddRequest`, named the same way as a derived response
+    assert_eq!(AddResponse::IDENTITY, "add");
+
+    let sum = SumResponse { sum: 3 };
+    let res = sum.into_response(0).unwrap();
+    assert_eq!(res.id, seraphic::RequestId::from(0));
+    assert_eq!(SumResponse::try_from_response(&res).unwrap().unwrap(), sum);
+    assert!(ProductResponse::try_from_response(&res).is_err());
+}
+
+#[seraphic::derive::rpc_request(namespace = "TestNS:test", response = "AddResponse", strict_params)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct AttrAddRequest {
+    a: i32,
+    b: i32,
+}
+
+#[test]
+fn rpc_request_attribute() {
+    assert_eq!(AttrAddRequest::namespace_method(), "test_attrAdd");
+    const { assert!(AttrAddRequest::STRICT_PARAMS) };
+    let req = AttrAddRequest { a: 1, b: 2 }.into_request(0).unwrap();
+    assert_eq!(
+        AttrAddRequest::try_from_request(&req).unwrap(),
+        AttrAddRequest { a: 1, b: 2 }
+    );
+}
+
+#[test]
+fn request_from_tuple() {
+    let add = AddRequest { a: 1, b: 2 };
+    let req: Request = (add.clone(), "42").into();
+    assert_eq!(req, add.into_request("42").unwrap());
+    assert_eq!(req.id, seraphic::RequestId::from("42"));
+
+    let req = Request::from((add.clone(), 7));
+    assert_eq!(req.id, seraphic::RequestId::from(7));
+    assert_eq!(AddRequest::try_from_request(&req).unwrap(), add);
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(
+    namespace = "TestNS:test",
+    response = "AddResponse",
+    naming = "snake_case"
+)]
+pub struct GetUserNameRequest {}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(
+    namespace = "TestNS:test",
+    response = "AddResponse",
+    naming = "kebab-case"
+)]
+pub struct GetUserIdRequest {}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(
+    namespace = "TestNS:test",
+    response = "AddResponse",
+    naming = "camelCase"
+)]
+pub struct GetUserAgeRequest {}
+
+#[test]
+fn method_naming() {
+    assert_eq!(GetUserNameRequest::method(), "get_user_name");
+    assert_eq!(GetUserIdRequest::method(), "get-user-id");
+    assert_eq!(GetUserAgeRequest::method(), "getUserAge");
+    assert_eq!(AddRequest::method(), "add");
+
+    let req = GetUserNameRequest {}.into_request(0).unwrap();
+    assert_eq!(req.method, "test_get_user_name");
+    assert_eq!(
+        GetUserNameRequest::try_from_request(&req).unwrap(),
+        GetUserNameRequest {}
+    );
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize)]
+#[rpc_request(namespace = "TestNS:test", response = "AddResponse", impl_debug)]
+pub struct DebugRequest {
+    a: i32,
+    name: String,
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize)]
+#[rpc_request(namespace = "TestNS:test", response = "ComputeResponse", impl_debug)]
+pub enum DebugEnumRequest {
+    Negate(i32),
+    Add { a: i32, b: i32 },
+    Zero,
+}
+
+#[test]
+fn derived_debug() {
+    let req = DebugRequest {
+        a: 1,
+        name: "one".to_string(),
+    };
+    assert_eq!(format!("{req:?}"), r#"DebugRequest { a: 1, name: "one" }"#);
+    assert_eq!(
+        format!("{req:#?}"),
+        "DebugRequest {\n    a: 1,\n    name: \"one\",\n}"
+    );
+
+    assert_eq!(format!("{:?}", DebugEnumRequest::Negate(1)), "Negate(1)");
+    assert_eq!(
+        format!("{:?}", DebugEnumRequest::Add { a: 1, b: 2 }),
+        "Add { a: 1, b: 2 }"
+    );
+    assert_eq!(format!("{:?}", DebugEnumRequest::Zero), "Zero");
+}
+
+#[test]
+fn request_with_optional_id() {
+    let add = AddRequest { a: 1, b: 2 };
+    let req = add.into_request_opt(None::<i64>).unwrap();
+    assert_eq!(req.id, seraphic::RequestId::Null);
+    assert_eq!(serde_json::to_value(&req).unwrap()["id"], json!(null));
+    assert_eq!(AddRequest::try_from_request(&req).unwrap(), add);
+
+    let req = add.into_request_opt(Some("abc")).unwrap();
+    assert_eq!(req, add.into_request("abc").unwrap());
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(
+    namespace = "TestNS:test",
+    response = "TestResponse",
+    assert_empty_params
+)]
+pub struct PingRequest {}
+
+#[test]
+fn empty_params() {
+    for params in [
+        json!({}),
+        json!({"extra": 1}),
+        json!([]),
+        json!([1]),
+        json!(null),
+    ] {
+        assert_eq!(PingRequest::try_from_json(&params).unwrap(), PingRequest {});
+    }
+}
+
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug)]
+#[rpc_request(namespace = "TestNS:test", response = "AddResponse", no_eq)]
+pub struct LookupRequest {
+    name: String,
+}
+
+impl PartialEq for LookupRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+    }
+}
+
+// what requests looked like before the derive implemented `PartialEq`
+#[derive(RpcRequest, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[rpc_request(namespace = "TestNS:test", response = "AddResponse", no_eq)]
+pub struct LegacyRequest {
+    name: String,
+}
+
+#[test]
+fn derived_eq() {
+    assert_eq!(AddRequest { a: 1, b: 2 }, AddRequest { a: 1, b: 2 });
+    assert_ne!(AddRequest { a: 1, b: 2 }, AddRequest { a: 2, b: 1 });
+
+    assert_eq!(ComputeRequest::Negate(1), ComputeRequest::Negate(1));
+    assert_ne!(ComputeRequest::Negate(1), ComputeRequest::Negate(2));
+    assert_ne!(
+        ComputeRequest::Add { a: 1, b: 2 },
+        ComputeRequest::Add { a: 1, b: 3 }
+    );
+    assert_ne!(ComputeRequest::Zero, ComputeRequest::Negate(0));
+    assert_eq!(ComputeRequest::Zero, ComputeRequest::Zero);
+
+    let lookup = |name: &str| LookupRequest {
+        name: name.to_string(),
+    };
+    assert_eq!(lookup("Alice"), lookup("alice"));
+    assert_ne!(lookup("Alice"), lookup("Bob"));
+
+    let legacy = |name: &str| LegacyRequest {
+        name: name.to_string(),
+    };
+    assert_ne!(legacy("Alice"), legacy("alice"));
+}
+
+#[test]
+fn response_unwraps() {
+    let res = SumResponse { sum: 3 }.into_response(0).unwrap();
+    assert_eq!(res.clone().unwrap_result(), json!({"sum": 3}));
+    assert_eq!(
+        res.clone().result_as::<SumResponse>().unwrap(),
+        SumResponse { sum: 3 }
+    );
+    assert!(res.result_as::<ProductResponse>().is_err());
+
+    let err = seraphic::error::Error::internal("boom");
+    let res = seraphic::Response::from_error(0, err.clone());
+    assert_eq!(res.unwrap_error(), err);
+}
+
+#[test]
+#[should_panic(expected = "expected result, got error")]
+fn unwrap_result_panics_on_error() {
+    let err = seraphic::error::Error::internal("boom");
+    seraphic::Response::from_error(0, err).unwrap_result();
+}
+
+#[test]
+#[should_panic(expected = "expected error, got result")]
+fn unwrap_error_panics_on_result() {
+    SumResponse { sum: 3 }
+        .into_response(0)
+        .unwrap()
+        .unwrap_error();
+}
+
+#[derive(RpcNamespace, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AliasedNS {
+    #[namespace(aliases = ["foo_ns", "fooNs"])]
+    FooNs,
+    Bar,
+}
+
+#[test]
+fn namespace_aliases() {
+    use seraphic::RpcNamespace;
+
+    for name in ["foons", "foo_ns", "fooNs"] {
+        assert_eq!(AliasedNS::try_from_str(name), Some(AliasedNS::FooNs));
+    }
+    assert_eq!(AliasedNS::FooNs.as_str(), "foons");
+    assert_eq!(AliasedNS::FOONS, "foons");
+    assert_eq!(AliasedNS::try_from_str("bar"), Some(AliasedNS::Bar));
+    assert_eq!(AliasedNS::try_from_str("FooNs"), None);
+}
+
+#[test]
+fn params_hash() {
+    let hash = AddRequest { a: 1, b: 2 }.hash_params();
+    assert_eq!(hash, AddRequest { a: 1, b: 2 }.hash_params());
+    assert_ne!(hash, AddRequest { a: 2, b: 1 }.hash_params());
+    assert_eq!(TestRequest {}.hash_params(), TestRequest {}.hash_params());
+
+    // enums use the default, which hashes the whole serialized request
+    let negate = ComputeRequest::Negate(1).hash_params();
+    assert_eq!(negate, ComputeRequest::Negate(1).hash_params());
+    assert_ne!(negate, ComputeRequest::Negate(2).hash_params());
+    assert_ne!(negate, ComputeRequest::Zero.hash_params());
+
+    // a retried request hashes the same once it has been through the wire
+    let req = AddRequest { a: 1, b: 2 }.into_request(0).unwrap();
+    assert_eq!(
+        AddRequest::try_from_request(&req).unwrap().hash_params(),
+        hash
+    );
+}
+
+// rustc strips cfg'd out variants before running derives, so nothing is generated for them
+#[derive(Debug, Clone, PartialEq, RequestWrapper)]
+enum ConfiguredRequest {
+    Test(TestRequest),
+    #[cfg(any())]
+    Missing(NotARequest),
+}
+
+#[derive(Debug, Clone, PartialEq, ResponseWrapper)]
+enum ConfiguredResponse {
+    Test(TestResponse),
+    #[cfg(any())]
+    Missing(NotAResponse),
+}
+
+#[test]
+fn wrapper_cfg_variants() {
+    use seraphic::RequestWrapper as _;
+
+    let req = ConfiguredRequest::from(TestRequest {}).into_req(1);
+    assert_eq!(
+        ConfiguredRequest::try_from_req(req).unwrap(),
+        ConfiguredRequest::Test(TestRequest {})
+    );
+    let res = ConfiguredResponse::from(TestResponse {}).into_res(1);
+    assert_eq!(
+        ConfiguredResponse::try_from_res(res).unwrap(),
+        Ok(ConfiguredResponse::Test(TestResponse {}))
+    );
+}
@@ -0,0 +1,174 @@
+use super::*;
+use seraphic::{
+    error::ErrorCode,
+    quic::{
+        quinn::{
+            self,
+            rustls::{
+                pki_types::{CertificateDer, PrivatePkcs8KeyDer},
+                RootCertStore,
+            },
+        },
+        QuicConnection, QuicServer,
+    },
+    RequestWrapper,
+};
+use std::sync::Arc;
+
+/// A server config with a self-signed certificate for "localhost", and a client config trusting it
+fn configs() -> (quinn::ServerConfig, quinn::ClientConfig) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = CertificateDer::from(cert.cert);
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let server = quinn::ServerConfig::with_single_cert(vec![cert_der.clone()], key.into()).unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert_der).unwrap();
+    let client = quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap();
+    (server, client)
+}
+
+#[tokio::test]
+async fn quic_transport() {
+    let (server_config, client_config) = configs();
+    let server =
+        QuicServer::<MyRequest, MyResponse>::bind(server_config, ([127, 0, 0, 1], 0).into())
+            .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let handler = tokio::spawn(async move {
+        let conn = server.accept().await.unwrap().unwrap();
+        conn.accept_initialize::<TestRequest>(TestResponse {})
+            .await
+            .unwrap();
+        while let Some(msg) = conn.recv().await.unwrap() {
+            if let Message::Req {
+                id,
+                req: MyRequest::Foo(_),
+            } = msg
+            {
+                let res = MyResponse::from(FooResponse {}).into_message(id);
+                conn.send(&res).await.unwrap();
+            }
+        }
+    });
+
+    let client = QuicConnection::<MyRequest, MyResponse>::connect(client_config, addr, "localhost")
+        .await
+        .unwrap();
+    // the server only initializes with a TestRequest
+    let err = client.initialize(FooRequest {}).await.unwrap().unwrap_err();
+    assert_eq!(
+        err.code,
+        ErrorCode::ServerError(ErrorCode::SERVER_ERROR_START)
+    );
+    assert_eq!(
+        client.initialize(TestRequest {}).await.unwrap(),
+        Ok(TestResponse {})
+    );
+
+    for id in 1..4 {
+        client
+            .send(&MyRequest::from(FooRequest {}).into_message(id))
+            .await
+            .unwrap();
+    }
+    for id in 1..4 {
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Some(MyResponse::from(FooResponse {}).into_message(id))
+        );
+    }
+
+    client.close();
+    handler.await.unwrap();
+}
+
+async fn send_raw(conn: &quinn::Connection, bytes: &[u8]) {
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(bytes).await.unwrap();
+    stream.finish().unwrap();
+}
+
+async fn recv_raw(conn: &quinn::Connection) -> serde_json::Value {
+    let mut stream = conn.accept_uni().await.unwrap();
+    serde_json::from_slice(&stream.read_to_end(1024 * 1024).await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn quic_malformed_messages_are_answered() {
+    let (server_config, client_config) = configs();
+    let server =
+        QuicServer::<MyRequest, MyResponse>::bind(server_config, ([127, 0, 0, 1], 0).into())
+            .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let handler = tokio::spawn(async move {
+        let conn = server.accept().await.unwrap().unwrap();
+        conn.accept_initialize::<TestRequest>(TestResponse {})
+            .await
+            .unwrap();
+        // the client ends up sending one message too many
+        assert!(matches!(
+            conn.recv().await,
+            Err(seraphic::SeraphicError::Protocol(_))
+        ));
+    });
+
+    let mut endpoint = quinn::Endpoint::client(([127, 0, 0, 1], 0).into()).unwrap();
+    endpoint.set_default_client_config(client_config);
+    let conn = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+    // not JSON is a parse error, JSON that isn't a message an invalid request
+    for (bytes, code) in [
+        (&b"not json"[..], ErrorCode::ParseError),
+        (&b"{}"[..], ErrorCode::InvalidRequest),
+    ] {
+        send_raw(&conn, bytes).await;
+        let res: seraphic::Response = serde_json::from_value(recv_raw(&conn).await).unwrap();
+        assert_eq!(res.id, seraphic::RequestId::Null);
+        assert_eq!(res.error.unwrap().code, code);
+    }
+
+    // and the handler carries on
+    let init: Message = MyRequest::from(TestRequest {}).into_message(0);
+    send_raw(&conn, &serde_json::to_vec(&init).unwrap()).await;
+    let res: Message = serde_json::from_value(recv_raw(&conn).await).unwrap();
+    assert_eq!(res, MyResponse::from(TestResponse {}).into_message(0));
+
+    for _ in 0..seraphic::tokio::server::DEFAULT_MAX_MALFORMED_PACKETS {
+        send_raw(&conn, b"{}").await;
+    }
+    handler.await.unwrap();
+}
+
+#[tokio::test]
+async fn quic_unfinished_streams_dont_block() {
+    let (server_config, client_config) = configs();
+    let server = QuicServer::<MyRequest, MyResponse>::bind_with(
+        server_config,
+        ([127, 0, 0, 1], 0).into(),
+        Some(std::time::Duration::from_millis(100)),
+    )
+    .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut endpoint = quinn::Endpoint::client(([127, 0, 0, 1], 0).into()).unwrap();
+    endpoint.set_default_client_config(client_config);
+    let (conn, accepted) = tokio::join!(
+        async { endpoint.connect(addr, "localhost").unwrap().await.unwrap() },
+        server.accept()
+    );
+    let accepted = accepted.unwrap().unwrap();
+
+    // half a message on a stream that is never finished, then a whole one after it
+    let mut unfinished = conn.open_uni().await.unwrap();
+    unfinished.write_all(b"{\"jsonrpc\"").await.unwrap();
+    let msg: Message = MyRequest::from(FooRequest {}).into_message(1);
+    send_raw(&conn, &serde_json::to_vec(&msg).unwrap()).await;
+
+    let received = tokio::time::timeout(std::time::Duration::from_secs(5), accepted.recv())
+        .await
+        .expect("the unfinished stream held up the one after it")
+        .unwrap();
+    assert_eq!(received, Some(msg));
+}
@@ -54,3 +54,50 @@ fn message_serde() {
         panic!()
     }
 }
+
+#[test]
+fn request_ids() {
+    use seraphic::{Request, RequestId};
+
+    for (raw, expected) in [
+        (serde_json::json!(7), RequestId::from(7)),
+        (serde_json::json!(u64::MAX), RequestId::from(u64::MAX)),
+        (serde_json::json!("seven"), RequestId::from("seven")),
+        (serde_json::Value::Null, RequestId::Null),
+    ] {
+        let json = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "test_test",
+            "params": {},
+            "id": raw,
+        });
+        let req: Request = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(req.id, expected);
+        assert_eq!(serde_json::to_value(&req).unwrap(), json);
+    }
+
+    // a notification leaves the id out
+    let notification = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "test_test",
+        "params": {},
+    });
+    let req: Request = serde_json::from_value(notification).unwrap();
+    assert_eq!(req.id, RequestId::Null);
+}
+
+#[test]
+fn messages_roundtrip() {
+    use seraphic::{error::ErrorCode, testing::assert_roundtrip, ResponseWrapper};
+
+    assert_roundtrip(&MyRequest::from(FooRequest {}).into_message::<MyResponse>(1));
+    assert_roundtrip(&MyResponse::from(FooResponse {}).into_message::<MyRequest>("two"));
+    assert_roundtrip::<MyRequest, MyResponse>(&Message::Err {
+        id: 3.into(),
+        err: seraphic::error::Error {
+            code: ErrorCode::InternalError,
+            message: "oops".to_string(),
+            data: None,
+        },
+    });
+}
@@ -0,0 +1,69 @@
+use super::{async_server::connect_and_initialize, *};
+use seraphic::{
+    error::{Error, ErrorCode},
+    packet::PacketRead,
+    tokio::{AsyncServerConnection, Server, ServerConnectionHandler, ServerHandlerResult},
+    tower::{serve, SeraphicService},
+    Request, RequestWrapper, Response,
+};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tower::ServiceBuilder;
+
+pub struct TowerHandler;
+
+impl ServerConnectionHandler<MyRequest, MyResponse> for TowerHandler {
+    async fn handler(
+        conn: &mut AsyncServerConnection<MyRequest, MyResponse>,
+    ) -> ServerHandlerResult {
+        conn.initialize::<TestRequest>(TestResponse {}).await?;
+        let service = ServiceBuilder::new()
+            .timeout(Duration::from_millis(50))
+            .service(SeraphicService::new(|req: Request| async move {
+                if FooRequest::try_from_request(&req).is_ok() {
+                    return Ok(Response::from_res(req.id, FooResponse {}));
+                }
+                if TestRequest::try_from_request(&req).is_ok() {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    return Ok(Response::from_res(req.id, TestResponse {}));
+                }
+                Err(Error::method_not_found(&req.method))
+            }));
+        serve(conn, service).await
+    }
+}
+
+async fn recv_response(stream: &mut TcpStream) -> Response {
+    match seraphic::packet::TcpPacket::<Response>::async_read(stream)
+        .await
+        .unwrap()
+    {
+        PacketRead::Message(res) => res,
+        other => panic!("expected a response, got: {other:#?}"),
+    }
+}
+
+#[tokio::test]
+async fn tower_service_with_middleware() {
+    let mut server = Server::<MyRequest, MyResponse, TowerHandler>::listen("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (mut stream, ()) = tokio::join!(connect_and_initialize(addr), async {
+        server.next().await.unwrap().unwrap();
+    });
+
+    let foo = MyRequest::from(FooRequest {}).into_message(1);
+    MessagePacket::async_write(&mut stream, &foo).await.unwrap();
+    let res = recv_response(&mut stream).await;
+    assert_eq!(res, Response::from_res(1, FooResponse {}));
+
+    // the timeout layer cuts this one short
+    let slow = MyRequest::from(TestRequest {}).into_message(2);
+    MessagePacket::async_write(&mut stream, &slow)
+        .await
+        .unwrap();
+    let res = recv_response(&mut stream).await;
+    assert_eq!(res.id, 2.into());
+    assert_eq!(res.error.unwrap().code, ErrorCode::InternalError);
+}
@@ -1,10 +1,10 @@
 use seraphic::packet::{PacketRead, TcpPacket};
 use serde::{Deserialize, Serialize};
-use std::io::{BufReader, Write};
+use std::io::BufReader;
 use std::net::{TcpListener, TcpStream};
 use std::thread;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct TestData {
     id: u32,
     message: String,
@@ -24,7 +24,8 @@ fn handle_client(mut stream: TcpStream) {
 
 #[test]
 fn test_tcp_packet_read_write() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
 
     thread::spawn(move || {
         for stream in listener.incoming() {
@@ -37,10 +38,102 @@ fn test_tcp_packet_read_write() {
 
     thread::sleep(std::time::Duration::from_millis(100));
 
-    let mut stream = TcpStream::connect("127.0.0.1:7878").unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
     let test_data = TestData {
         id: 1,
         message: "Hello".into(),
     };
     TcpPacket::write(&mut stream, &test_data).unwrap();
 }
+
+/// Hands out `chunks` one per `read`, with a `WouldBlock` between each, like a nonblocking
+/// socket receiving a segmented packet
+struct Segmented {
+    chunks: std::collections::VecDeque<Vec<u8>>,
+    blocked: bool,
+}
+
+impl std::io::Read for Segmented {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.blocked = !self.blocked;
+        if self.blocked {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let Some(chunk) = self.chunks.pop_front() else {
+            return Ok(0);
+        };
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[test]
+fn read_buffered_survives_partial_headers() {
+    let data = TestData {
+        id: 1,
+        message: "Hello".into(),
+    };
+    let mut bytes = vec![];
+    TcpPacket::write(&mut bytes, &data).unwrap();
+    TcpPacket::write(&mut bytes, &data).unwrap();
+    // the first header arrives in two pieces, the second packet with the end of the first
+    let split = [1, 3, bytes.len() / 2 + 2];
+    let mut chunks = std::collections::VecDeque::new();
+    let mut start = 0;
+    for end in split.into_iter().chain([bytes.len()]) {
+        chunks.push_back(bytes[start..end].to_vec());
+        start = end;
+    }
+    let mut inp = Segmented {
+        chunks,
+        blocked: false,
+    };
+
+    let mut buffer = seraphic::packet::PacketBuffer::new();
+    let mut read = vec![];
+    loop {
+        match TcpPacket::<TestData>::read_buffered(&mut inp, &mut buffer).unwrap() {
+            PacketRead::Empty => continue,
+            PacketRead::Disconnected => break,
+            other => read.push(other),
+        }
+    }
+    assert_eq!(
+        read,
+        [PacketRead::Message(data.clone()), PacketRead::Message(data)]
+    );
+}
+
+#[test]
+fn read_disconnected_empty_and_malformed() {
+    let mut closed: &[u8] = &[];
+    assert_eq!(
+        TcpPacket::<TestData>::read(&mut closed).unwrap(),
+        PacketRead::Disconnected
+    );
+
+    let mut blocked = BufReader::new(Segmented {
+        chunks: Default::default(),
+        blocked: false,
+    });
+    assert_eq!(
+        TcpPacket::<TestData>::read(&mut blocked).unwrap(),
+        PacketRead::Empty
+    );
+
+    let data = TestData {
+        id: 1,
+        message: "Hello".into(),
+    };
+    let mut bytes = vec![2, 0, 0, 0, b'{', b'}'];
+    TcpPacket::write(&mut bytes, &data).unwrap();
+    let mut inp = bytes.as_slice();
+    assert!(matches!(
+        TcpPacket::<TestData>::read(&mut inp).unwrap(),
+        PacketRead::Malformed { payload, .. } if payload == b"{}"
+    ));
+    assert_eq!(
+        TcpPacket::<TestData>::read(&mut inp).unwrap(),
+        PacketRead::Message(data)
+    );
+}
@@ -0,0 +1,143 @@
+use super::{async_server::EchoHandler, *};
+use seraphic::{
+    tokio::{ClientConnection, Server},
+    RequestWrapper,
+};
+use std::time::Duration;
+
+async fn ws_server() -> (
+    Server<MyRequest, MyResponse, EchoHandler>,
+    std::net::SocketAddr,
+) {
+    let server = Server::<MyRequest, MyResponse, EchoHandler>::listen_ws("127.0.0.1:0", "/rpc")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    (server, addr)
+}
+
+#[tokio::test]
+async fn ws_initialize_request_shutdown() {
+    let (mut server, addr) = ws_server().await;
+    let url = format!("ws://{addr}/rpc");
+    let (conn, accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect_ws(&url),
+        server.next()
+    );
+    let conn = conn.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    assert_eq!(
+        conn.initialize(TestRequest {}).await.unwrap(),
+        Ok(TestResponse {})
+    );
+    for id in 1..4 {
+        conn.sender()
+            .send(MyRequest::from(FooRequest {}).into_message(id))
+            .await
+            .unwrap();
+    }
+    for id in 1..4 {
+        assert_eq!(
+            conn.receiver().lock().await.recv().await,
+            Some(MyResponse::from(FooResponse {}).into_message(id))
+        );
+    }
+
+    // the handler sees the client go away once the connection is shut down
+    conn.shutdown(Duration::from_secs(5)).await.unwrap();
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn ws_wrong_path_is_refused() {
+    let (mut server, addr) = ws_server().await;
+    let url = format!("ws://{addr}/elsewhere");
+    let (conn, accepted) = tokio::join!(
+        ClientConnection::<MyRequest, MyResponse>::connect_ws(&url),
+        server.next()
+    );
+    assert!(conn.is_err());
+    let client_addr = accepted.unwrap().unwrap();
+    assert!(server.join_connection(&client_addr).await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn ws_frames_and_pings() {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as Frame;
+
+    let (mut server, addr) = ws_server().await;
+    let url = format!("ws://{addr}/rpc");
+    let (ws, accepted) = tokio::join!(tokio_tungstenite::connect_async(&url), server.next());
+    let (mut ws, _) = ws.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    ws.send(Frame::Ping("still there?".into())).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Frame::Pong("still there?".into())
+    );
+
+    // one message per frame, text or binary
+    let init: Message = MyRequest::from(TestRequest {}).into_message(0);
+    let init = serde_json::to_vec(&init).unwrap();
+    ws.send(Frame::binary(init)).await.unwrap();
+    let Frame::Text(text) = ws.next().await.unwrap().unwrap() else {
+        panic!("expected a text frame");
+    };
+    assert_eq!(
+        serde_json::from_str::<Message>(text.as_str()).unwrap(),
+        MyResponse::from(TestResponse {}).into_message(0)
+    );
+
+    ws.close(None).await.unwrap();
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn ws_server_closes_normally() {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message as Frame};
+
+    let (mut server, addr) = ws_server().await;
+    let url = format!("ws://{addr}/rpc");
+    let (ws, accepted) = tokio::join!(tokio_tungstenite::connect_async(&url), server.next());
+    let (mut ws, _) = ws.unwrap();
+    let client_addr = accepted.unwrap().unwrap();
+
+    let init: Message = MyRequest::from(TestRequest {}).into_message(0);
+    ws.send(Frame::text(serde_json::to_string(&init).unwrap()))
+        .await
+        .unwrap();
+    assert!(matches!(ws.next().await, Some(Ok(Frame::Text(_)))));
+
+    // the handler returns, and the client is told with a close handshake
+    assert!(server.disconnect(&client_addr, true));
+    server.join_connection(&client_addr).await.unwrap().unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(5), ws.next())
+        .await
+        .unwrap();
+    let Some(Ok(Frame::Close(Some(close)))) = frame else {
+        panic!("expected a close frame, got {frame:?}");
+    };
+    assert_eq!(close.code, CloseCode::Normal);
+    assert!(ws.next().await.is_none());
+}
+
+#[tokio::test]
+async fn ws_client_vanishing_ends_handler() {
+    let (mut server, addr) = ws_server().await;
+    let url = format!("ws://{addr}/rpc");
+    let (ws, accepted) = tokio::join!(tokio_tungstenite::connect_async(&url), server.next());
+    let client_addr = accepted.unwrap().unwrap();
+
+    // no close frame, the socket just goes away
+    drop(ws.unwrap());
+    let joined = tokio::time::timeout(Duration::from_secs(5), server.join_connection(&client_addr))
+        .await
+        .unwrap()
+        .unwrap();
+    // the handler was still waiting to be initialized
+    assert!(joined.is_err());
+}